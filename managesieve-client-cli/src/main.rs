@@ -5,22 +5,30 @@ use std::ffi::{OsStr, OsString};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::pin::{Pin, pin};
+use std::sync::Arc;
 use std::{mem, num};
 
 use clap::{Args, Command, Parser, Subcommand, arg};
 use color_eyre::eyre;
 use color_eyre::eyre::{WrapErr, bail, eyre};
-use managesieve::commands::{Authenticate, CheckScript, HaveSpace, PutScript};
-use managesieve::sasl::{InitialSaslState, Sasl, SaslError, SaslFn, SaslState};
+use managesieve::commands::{
+    Authenticate, CheckScript, ConnectOptions, DeleteScript, HaveSpace, PutScript, RenameScript,
+    SetActive,
+};
+use managesieve::pinning::{
+    CertificatePin, FileFingerprintStore, Fingerprint, PinnedVerifier, TofuVerifier,
+};
+use managesieve::sasl::{InitialSaslState, Plain, Sasl, SaslError, SaslFn, SaslState};
 use managesieve::state::{Authenticated, Tls, TlsMode, Unauthenticated};
 use managesieve::{
-    AsyncRead, AsyncWrite, Connection, Quota, ServerName, SieveNameStr, SieveNameString,
+    AsyncRead, AsyncWrite, Connection, Quota, ServerName, Session, SieveNameStr, SieveNameString,
+    Timeouts,
 };
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 use tracing::{Level, debug, info};
 use tracing_subscriber::util::SubscriberInitExt;
 
@@ -36,13 +44,33 @@ struct Arguments {
     port: u16,
 
     /// Don't use STARTLS
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, conflicts_with = "implicit_tls")]
     no_tls: bool,
 
+    /// Use TLS from the start of the connection instead of STARTTLS
+    #[arg(long, default_value_t = false)]
+    implicit_tls: bool,
+
+    /// Only accept a server certificate with this SHA-256 fingerprint
+    #[arg(long, conflicts_with = "no_tls")]
+    pin: Vec<Fingerprint>,
+
+    /// Only accept a server certificate whose public key has this SHA-256 fingerprint
+    #[arg(long, conflicts_with = "no_tls")]
+    pin_public_key: Vec<Fingerprint>,
+
+    /// Trust the server certificate on first use and remember its fingerprint in this file
+    #[arg(long, conflicts_with_all = ["no_tls", "pin", "pin_public_key"])]
+    known_fingerprints: Option<PathBuf>,
+
     /// Sieve user name
     #[arg(long, short, required = false)]
     user: Option<String>,
 
+    /// Act on the scripts of this user instead, if the server allows it for `user`
+    #[arg(long, requires = "user")]
+    authzid: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -89,6 +117,37 @@ enum Commands {
         #[arg(long, default_value_t = false)]
         overwrite: bool,
     },
+
+    /// Mark the specified script as active
+    #[command()]
+    Activate {
+        /// Script name
+        #[arg()]
+        name: SieveNameString,
+    },
+
+    /// Deactivate all scripts
+    #[command()]
+    Deactivate,
+
+    /// Delete the specified script
+    #[command()]
+    Delete {
+        /// Script name
+        #[arg()]
+        name: SieveNameString,
+    },
+
+    /// Rename the specified script
+    #[command()]
+    Rename {
+        /// Current script name
+        #[arg()]
+        old_name: SieveNameString,
+        /// New script name
+        #[arg()]
+        new_name: SieveNameString,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -106,43 +165,75 @@ pub async fn main() -> eyre::Result<()> {
         .context("failed to resolve address")?;
     let tcp = tcp.compat();
 
-    let sieve = Connection::connect(tcp).await?;
+    let mut options = ConnectOptions {
+        tls_config: None,
+        timeouts: Some(Timeouts::new(tokio::time::sleep)),
+    };
 
     if args.no_tls {
-        continue_tls(args.user, args.command, sieve).await?;
+        let sieve = Connection::connect_with_options(tcp, options).await?;
+        continue_tls(args.user, args.authzid, args.command, sieve).await?;
     } else {
         let server_name =
             ServerName::try_from(args.address).context("failed to parse server name")?;
-        let sieve = sieve.start_tls(server_name).await?;
-        continue_tls(args.user, args.command, sieve).await?;
+        let pins = args
+            .pin
+            .into_iter()
+            .map(CertificatePin::Certificate)
+            .chain(args.pin_public_key.into_iter().map(CertificatePin::PublicKey))
+            .collect::<Vec<_>>();
+        options.tls_config = if !pins.is_empty() {
+            Some(PinnedVerifier::new(pins).into_config())
+        } else {
+            args.known_fingerprints.map(|path| {
+                TofuVerifier::new(Arc::new(FileFingerprintStore::new(path))).into_config()
+            })
+        };
+
+        let sieve = if args.implicit_tls {
+            Connection::connect_tls_with_options(tcp, server_name, options).await?
+        } else {
+            let sieve = Connection::connect_with_options(tcp, options).await?;
+            sieve.start_tls(server_name).await?
+        };
+        continue_tls(args.user, args.authzid, args.command, sieve).await?;
     }
 
     async fn continue_tls<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
         user: Option<String>,
+        authzid: Option<String>,
         commands: Commands,
         sieve: Connection<STREAM, TLS, Unauthenticated>,
     ) -> eyre::Result<()> {
         if let Some(user) = user {
             let password = rpassword::prompt_password(format!("password for `{user}`:"))?;
-            let init = format!("\0{}\0{}", user, password);
-            let sasl = ("PLAIN", init.as_bytes());
-            let sieve = match sieve.authenticate(sasl).await? {
-                Authenticate::Ok { connection } => connection,
+            let sasl = match authzid {
+                Some(authzid) => Plain::with_authzid(&authzid, &user, &password)?,
+                None => Plain::new(&user, &password)?,
+            };
+            let mut sieve = match sieve.authenticate(&sasl).await? {
+                Authenticate::Ok { connection } => connection.into_session(),
                 Authenticate::Error { error, .. } => return Err(error.into()),
             };
 
             match commands {
                 Commands::Info => println!("{:#?}", sieve.capabilities()),
-                Commands::List => list_scripts(sieve).await?,
+                Commands::List => list_scripts(&mut sieve).await?,
                 Commands::Get { name, output } => {
-                    get_script(sieve, name, output).await?;
+                    get_script(&mut sieve, name, output).await?;
                 }
-                Commands::Check { path } => check_script(sieve, path).await?,
+                Commands::Check { path } => check_script(&mut sieve, path).await?,
                 Commands::Put {
                     name,
                     path,
                     overwrite,
-                } => put_script(sieve, name, path, overwrite).await?,
+                } => put_script(&mut sieve, name, path, overwrite).await?,
+                Commands::Activate { name } => set_active(&mut sieve, Some(name)).await?,
+                Commands::Deactivate => set_active(&mut sieve, None).await?,
+                Commands::Delete { name } => delete_script(&mut sieve, name).await?,
+                Commands::Rename { old_name, new_name } => {
+                    rename_script(&mut sieve, old_name, new_name).await?
+                }
             }
         } else {
             match commands {
@@ -157,9 +248,9 @@ pub async fn main() -> eyre::Result<()> {
 }
 
 async fn list_scripts<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
-    sieve: Connection<STREAM, TLS, Authenticated>,
+    sieve: &mut Session<STREAM, TLS>,
 ) -> eyre::Result<()> {
-    let (_, scripts) = sieve.list_scripts().await?;
+    let scripts = sieve.list_scripts().await?;
     println!("Scripts:");
     println!("active name");
 
@@ -175,19 +266,39 @@ async fn list_scripts<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
 }
 
 async fn get_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
-    mut sieve: Connection<STREAM, TLS, Authenticated>,
+    sieve: &mut Session<STREAM, TLS>,
     name: SieveNameString,
     output: Option<PathBuf>,
 ) -> eyre::Result<()> {
-    let (_, script) = sieve.get_script(&name).await?;
+    if let Some(output) = output {
+        if fs::try_exists(&output).await? {
+            bail!("`{}` already exists", output.display());
+        }
 
-    if let Some(script) = script {
-        if let Some(output) = output {
-            let mut file = File::create_new(output).await?;
-            file.write_all(script.as_bytes()).await?;
-        } else {
-            println!("{}", script);
+        // the script is only moved to `output` once it was received completely
+        let mut partial = output.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        let file = File::create_new(&partial).await?;
+        match sieve.get_script_to(&name, file.compat_write()).await {
+            Ok(Some(_)) => fs::rename(&partial, &output).await?,
+            Ok(None) => {
+                fs::remove_file(&partial).await?;
+                println!("Script `{name}` does not exist");
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&partial).await;
+                return Err(err.into());
+            }
         }
+        return Ok(());
+    }
+
+    let script = sieve.get_script(&name).await?;
+
+    if let Some(script) = script {
+        println!("{}", script.to_string_lossy());
     } else {
         println!("Script `{name}` does not exist");
     }
@@ -196,12 +307,13 @@ async fn get_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
 }
 
 async fn check_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
-    mut sieve: Connection<STREAM, TLS, Authenticated>,
+    sieve: &mut Session<STREAM, TLS>,
     input: PathBuf,
 ) -> eyre::Result<()> {
-    let script = fs::read_to_string(input).await?;
+    let script = File::open(input).await?;
+    let len = script.metadata().await?.len().try_into()?;
 
-    let (_, result) = sieve.check_script(&script).await?;
+    let result = sieve.check_script_from(len, script.compat()).await?;
 
     match result {
         CheckScript::Ok { warnings } => {
@@ -222,7 +334,7 @@ async fn check_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
 }
 
 async fn put_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
-    mut sieve: Connection<STREAM, TLS, Authenticated>,
+    sieve: &mut Session<STREAM, TLS>,
     name: SieveNameString,
     input: PathBuf,
     overwrite: bool,
@@ -239,24 +351,24 @@ async fn put_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
         }
     }
 
-    let script = fs::read_to_string(input).await?;
+    let script = File::open(input).await?;
+    let len = script.metadata().await?.len().try_into()?;
 
     if !overwrite {
-        let (s, scripts) = sieve.list_scripts().await?;
-        sieve = s;
+        let scripts = sieve.list_scripts().await?;
         if scripts.into_iter().any(|(n, _)| name == n) {
             println!("Cannot upload script. Script `{name}` already exists");
             return Ok(());
         }
     }
 
-    let (sieve, havespace) = sieve.have_space(&name, script.len().try_into()?).await?;
+    let havespace = sieve.have_space(&name, len).await?;
     if let HaveSpace::InsufficientQuota { quota, message } = havespace {
         handle_quota(quota, message);
         return Ok(());
     }
 
-    let (sieve, result) = sieve.put_scripts(&name, &script).await?;
+    let result = sieve.put_script_from(&name, len, script.compat()).await?;
     match result {
         PutScript::Ok { warnings } => {
             println!("Successfully uploaded script.");
@@ -278,6 +390,85 @@ async fn put_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
     Ok(())
 }
 
+async fn set_active<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
+    sieve: &mut Session<STREAM, TLS>,
+    name: Option<SieveNameString>,
+) -> eyre::Result<()> {
+    let result = match &name {
+        Some(name) => sieve.set_active(name).await?,
+        None => sieve.deactivate_all().await?,
+    };
+
+    match (result, name) {
+        (SetActive::Ok, Some(name)) => println!("Script `{name}` is now active."),
+        (SetActive::Ok, None) => println!("All scripts are now inactive."),
+        (SetActive::Nonexistent { .. }, Some(name)) => println!("Script `{name}` does not exist"),
+        (SetActive::Nonexistent { message }, None) | (SetActive::Other { message }, _) => {
+            println!("Could not change active script.");
+            if let Some(message) = message {
+                println!("{message}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn delete_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
+    sieve: &mut Session<STREAM, TLS>,
+    name: SieveNameString,
+) -> eyre::Result<()> {
+    let result = sieve.delete_script(&name).await?;
+
+    match result {
+        DeleteScript::Ok => println!("Successfully deleted script `{name}`."),
+        DeleteScript::Active { .. } => {
+            println!("Cannot delete script. Script `{name}` is active.")
+        }
+        DeleteScript::Nonexistent { .. } => println!("Script `{name}` does not exist"),
+        DeleteScript::Other { message } => {
+            println!("Could not delete script.");
+            if let Some(message) = message {
+                println!("{message}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn rename_script<STREAM: AsyncWrite + AsyncRead + Unpin, TLS: TlsMode>(
+    sieve: &mut Session<STREAM, TLS>,
+    old_name: SieveNameString,
+    new_name: SieveNameString,
+) -> eyre::Result<()> {
+    let result = sieve.rename_script(&old_name, &new_name).await?;
+
+    match result {
+        RenameScript::Ok => println!("Successfully renamed script `{old_name}` to `{new_name}`."),
+        RenameScript::Nonexistent { .. } => println!("Script `{old_name}` does not exist"),
+        RenameScript::AlreadyExists { .. } => {
+            println!("Cannot rename script. Script `{new_name}` already exists")
+        }
+        RenameScript::Other { message } => {
+            println!("Could not rename script.");
+            if let Some(message) = message {
+                println!("{message}");
+            }
+        }
+        RenameScript::Duplicated { message } => {
+            println!(
+                "Could not rename script. Both `{old_name}` and the copy `{new_name}` exist now."
+            );
+            if let Some(message) = message {
+                println!("{message}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 // let f: impl for<'a> Fn(&'a [u8]) -> CoroutineState<Vec<u8>, Result<Option<Vec<u8>>, SaslError>> =
 //     |_input| return CoroutineState::Complete(Err(SaslError::UnexpectedServerResponse));
 
@@ -13,6 +13,7 @@ pub struct Capabilities {
     pub notify: Option<Vec<String>>,
     pub language: Option<String>,
     pub owner: Option<String>,
+    /// `0.0` if the server does not announce `VERSION`, see [`Capabilities::supports_rfc5804`]
     pub version: Version,
     pub others: HashMap<String, Option<String>>,
 }
@@ -23,12 +24,18 @@ pub enum CapabilitiesError {
     MissingImplementation,
     #[error("capabilities response is missing required capability `SIEVE`")]
     MissingSieve,
-    #[error("capabilities response is missing required capability `VERSION`")]
-    MissingVersion,
     #[error("received duplicate capability `{capability}`")]
     DuplicateCapability { capability: String },
 }
 
+impl Capabilities {
+    /// Whether the server announces `VERSION` and thus implements RFC 5804. Older servers lack
+    /// e.g. `RENAMESCRIPT`.
+    pub fn supports_rfc5804(&self) -> bool {
+        self.version.major >= 1
+    }
+}
+
 pub(crate) fn verify_capabilities(
     capabilities: Vec<Capability>,
 ) -> Result<Capabilities, CapabilitiesError> {
@@ -87,8 +94,8 @@ pub(crate) fn verify_capabilities(
             }
         }
     }
-    match (implementation, sieve, version) {
-        (Some(implementation), Some(sieve), Some(version)) => Ok(Capabilities {
+    match (implementation, sieve) {
+        (Some(implementation), Some(sieve)) => Ok(Capabilities {
             implementation,
             sasl: sasl.unwrap_or_default(),
             sieve,
@@ -97,12 +104,11 @@ pub(crate) fn verify_capabilities(
             notify,
             language,
             owner,
-            version,
+            version: version.unwrap_or(Version { major: 0, minor: 0 }),
             others,
         }),
-        (None, _, _) => Err(CapabilitiesError::MissingImplementation),
-        (_, None, _) => Err(CapabilitiesError::MissingSieve),
-        (_, _, None) => Err(CapabilitiesError::MissingVersion),
+        (None, _) => Err(CapabilitiesError::MissingImplementation),
+        (_, None) => Err(CapabilitiesError::MissingSieve),
     }
 }
 
@@ -111,3 +117,32 @@ pub struct Version {
     pub major: u64,
     pub minor: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(version: Option<Version>) -> Capabilities {
+        let mut capabilities = vec![
+            Capability::Implementation("test".into()),
+            Capability::Sieve(vec!["fileinto".into()]),
+        ];
+        capabilities.extend(version.map(Capability::Version));
+        verify_capabilities(capabilities).unwrap()
+    }
+
+    #[test]
+    fn test_version() {
+        let capabilities = capabilities(Some(Version { major: 1, minor: 0 }));
+        assert_eq!(capabilities.version, Version { major: 1, minor: 0 });
+        assert!(capabilities.supports_rfc5804());
+    }
+
+    // servers predating RFC 5804 do not announce `VERSION`
+    #[test]
+    fn test_missing_version() {
+        let capabilities = capabilities(None);
+        assert_eq!(capabilities.version, Version { major: 0, minor: 0 });
+        assert!(!capabilities.supports_rfc5804());
+    }
+}
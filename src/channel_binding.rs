@@ -0,0 +1,240 @@
+use futures_rustls::pki_types::CertificateDer;
+use futures_rustls::rustls::{ClientConnection, ProtocolVersion, SupportedCipherSuite};
+use ring::digest;
+
+use crate::state::{AuthMode, Tls};
+use crate::{AsyncRead, AsyncWrite, Connection};
+
+/// Channel binding types defined for TLS, see RFC 5929 and RFC 9266.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelBindingType {
+    TlsExporter,
+    TlsServerEndPoint,
+}
+
+impl ChannelBindingType {
+    /// name used by SASL mechanisms like SCRAM to identify the channel binding type
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelBindingType::TlsExporter => "tls-exporter",
+            ChannelBindingType::TlsServerEndPoint => "tls-server-end-point",
+        }
+    }
+
+    // `tls-exporter` needs TLS 1.3, see `Connection::channel_binding`
+    pub(crate) fn for_protocol_version(version: Option<ProtocolVersion>) -> Self {
+        match version {
+            Some(ProtocolVersion::TLSv1_3) => ChannelBindingType::TlsExporter,
+            _ => ChannelBindingType::TlsServerEndPoint,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+pub enum ChannelBindingError {
+    #[error("`tls-exporter` channel binding requires TLS 1.3")]
+    ExporterNeedsTls13,
+
+    #[error("the server did not present a certificate")]
+    NoPeerCertificate,
+
+    #[error("failed to parse the signature algorithm of the server certificate")]
+    InvalidCertificate,
+
+    #[error(
+        "`tls-server-end-point` channel binding is undefined for the signature algorithm of the \
+    server certificate"
+    )]
+    UnsupportedSignatureAlgorithm,
+
+    #[error("failed to export keying material: {0}")]
+    Tls(#[from] futures_rustls::rustls::Error),
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, AUTH: AuthMode> Connection<STREAM, Tls, AUTH> {
+    fn tls_connection(&self) -> &ClientConnection {
+        self.stream.get_ref().1
+    }
+
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.tls_connection().protocol_version()
+    }
+
+    pub fn cipher_suite(&self) -> Option<SupportedCipherSuite> {
+        self.tls_connection().negotiated_cipher_suite()
+    }
+
+    /// certificate chain presented by the server, starting with its own certificate
+    pub fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        self.tls_connection().peer_certificates()
+    }
+
+    /// Channel binding data for SASL mechanisms like `SCRAM-SHA-256-PLUS`.
+    ///
+    /// Obtain it before calling [`Connection::authenticate`] and pass it to the [`Sasl`] instance.
+    ///
+    /// [`Sasl`]: crate::sasl::Sasl
+    pub fn channel_binding(
+        &self,
+        binding_type: ChannelBindingType,
+    ) -> Result<Vec<u8>, ChannelBindingError> {
+        match binding_type {
+            ChannelBindingType::TlsExporter => {
+                // without the extended master secret, the exported keying material of TLS 1.2 is
+                // not unique to the connection
+                if self.protocol_version() != Some(ProtocolVersion::TLSv1_3) {
+                    return Err(ChannelBindingError::ExporterNeedsTls13);
+                }
+                let data = self.tls_connection().export_keying_material(
+                    vec![0; 32],
+                    b"EXPORTER-Channel-Binding",
+                    None,
+                )?;
+                Ok(data)
+            }
+            ChannelBindingType::TlsServerEndPoint => {
+                let certificate = self
+                    .peer_certificates()
+                    .and_then(|certificates| certificates.first())
+                    .ok_or(ChannelBindingError::NoPeerCertificate)?;
+                let algorithm = end_point_hash(certificate)?;
+                Ok(digest::digest(algorithm, certificate).as_ref().to_vec())
+            }
+        }
+    }
+}
+
+// RFC 5929, section 4.1: the hash function of the certificate signature, with MD5 and SHA-1
+// replaced by SHA-256; the channel binding is undefined for signatures without a single hash
+// function like Ed25519
+fn end_point_hash(certificate: &[u8]) -> Result<&'static digest::Algorithm, ChannelBindingError> {
+    const MD5_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x04];
+    const SHA1_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05];
+    const SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+    const SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+    const SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+    const ECDSA_WITH_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x01];
+    const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+    const ECDSA_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04];
+
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+    let parse = || {
+        let (certificate, _) = der_element(certificate, 0x30)?;
+        let (_, rest) = der_element(certificate, 0x30)?;
+        let (algorithm, _) = der_element(rest, 0x30)?;
+        let (oid, _) = der_element(algorithm, 0x06)?;
+        Some(oid)
+    };
+
+    Ok(match parse().ok_or(ChannelBindingError::InvalidCertificate)? {
+        MD5_WITH_RSA | SHA1_WITH_RSA | SHA256_WITH_RSA | ECDSA_WITH_SHA1 | ECDSA_WITH_SHA256 => {
+            &digest::SHA256
+        }
+        SHA384_WITH_RSA | ECDSA_WITH_SHA384 => &digest::SHA384,
+        SHA512_WITH_RSA | ECDSA_WITH_SHA512 => &digest::SHA512,
+        _ => return Err(ChannelBindingError::UnsupportedSignatureAlgorithm),
+    })
+}
+
+// splits a DER element with the given tag into its content and the remaining input
+fn der_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, input) = input.split_first()?;
+    if actual_tag != tag {
+        return None;
+    }
+
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let (len_bytes, rest) = input.split_at_checked(usize::from(first & 0x7f))?;
+        if len_bytes.is_empty() || len_bytes.len() > size_of::<usize>() {
+            return None;
+        }
+        input = rest;
+        len_bytes.iter().fold(0, |len, &byte| len << 8 | usize::from(byte))
+    };
+
+    input.split_at_checked(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA_SHA256: &[u8] = include_bytes!("../testdata/rsa-sha256.der");
+    const ECDSA_SHA384: &[u8] = include_bytes!("../testdata/ecdsa-sha384.der");
+    const ED25519: &[u8] = include_bytes!("../testdata/ed25519.der");
+
+    fn hash(certificate: &[u8]) -> Result<&'static digest::Algorithm, ChannelBindingError> {
+        end_point_hash(certificate)
+    }
+
+    #[test]
+    fn test_end_point_hash() {
+        assert_eq!(hash(RSA_SHA256), Ok(&digest::SHA256));
+        assert_eq!(hash(ECDSA_SHA384), Ok(&digest::SHA384));
+        assert_eq!(hash(ED25519), Err(ChannelBindingError::UnsupportedSignatureAlgorithm));
+    }
+
+    #[test]
+    fn test_end_point_hash_truncated() {
+        for certificate in [RSA_SHA256, ECDSA_SHA384, ED25519] {
+            for len in 0..certificate.len() {
+                assert_eq!(
+                    hash(&certificate[..len]),
+                    Err(ChannelBindingError::InvalidCertificate),
+                    "{len}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_der_element() {
+        assert_eq!(der_element(&[0x30, 0x02, 1, 2, 3], 0x30), Some((&[1, 2][..], &[3][..])));
+        assert_eq!(der_element(&[0x30, 0x00], 0x30), Some((&[][..], &[][..])));
+        // long form lengths
+        let long = [[0x30, 0x81, 0x80].as_slice(), &[7; 0x80]].concat();
+        assert_eq!(der_element(&long, 0x30), Some((&long[3..], &[][..])));
+        let long = [[0x30, 0x82, 0x01, 0x00].as_slice(), &[7; 0x100]].concat();
+        assert_eq!(der_element(&long, 0x30), Some((&long[4..], &[][..])));
+
+        for invalid in [
+            &[][..],
+            &[0x30],
+            // wrong tag
+            &[0x06, 0x00],
+            // content shorter than the length
+            &[0x30, 0x03, 1, 2],
+            // missing length bytes
+            &[0x30, 0x82, 0x01],
+            // indefinite length
+            &[0x30, 0x80, 1, 2, 0x00, 0x00],
+            // more length bytes than fit into `usize`
+            &[0x30, 0x89, 0, 0, 0, 0, 0, 0, 0, 0, 1, 7],
+            // lengths exceeding the input, including ones overflowing when shifted
+            &[0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 1],
+            &[
+                0x30, 0x88, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1,
+            ],
+        ] {
+            assert_eq!(der_element(invalid, 0x30), None, "{invalid:02x?}");
+        }
+    }
+
+    #[test]
+    fn test_binding_type_for_protocol_version() {
+        assert_eq!(
+            ChannelBindingType::for_protocol_version(Some(ProtocolVersion::TLSv1_3)),
+            ChannelBindingType::TlsExporter
+        );
+        for version in [Some(ProtocolVersion::TLSv1_2), None] {
+            assert_eq!(
+                ChannelBindingType::for_protocol_version(version),
+                ChannelBindingType::TlsServerEndPoint
+            );
+        }
+    }
+}
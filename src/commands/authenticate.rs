@@ -5,11 +5,11 @@ use base64::{engine, Engine};
 use commands::definitions;
 use either::Either;
 use engine::general_purpose;
+use futures::AsyncWriteExt;
 use general_purpose::STANDARD;
 
-use crate::capabilities::verify_capabilities;
-use crate::commands::{handle_bye, next_response};
-use crate::parser::responses::{response_authenticate, response_capability, response_nobye};
+use crate::commands::handle_bye;
+use crate::parser::responses::{response_authenticate, response_nobye};
 use crate::parser::{Response, Tag};
 use crate::sasl::{InitialSaslState, Sasl, SaslError};
 use crate::state::{Authenticated, TlsMode, Unauthenticated};
@@ -48,30 +48,41 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
         // TODO handle NO response specifically if initial message
 
         loop {
-            match next_response(&mut self.stream, response_authenticate).await? {
+            match self.next_response(response_authenticate).await? {
                 Either::Left(server_response) => {
                     // got SASL string
 
-                    if client_finished {
-                        // SASL is already finished, server should not send further challenge
+                    let Ok(server_challenge) = STANDARD.decode(server_response) else {
+                        self.cancel_authentication("*").await?;
                         return Ok(Authenticate::Error {
                             connection: Some(self),
                             error: SaslError::UnexpectedServerResponse,
                         });
+                    };
+
+                    if client_finished {
+                        // SASL is already finished, the server may only report an error
+                        let (error, client_response) =
+                            match sasl.as_mut().error_challenge(server_challenge) {
+                                Some((error, response)) => {
+                                    (SaslError::SaslError(error), STANDARD.encode(response))
+                                }
+                                None => (SaslError::UnexpectedServerResponse, "*".to_string()),
+                            };
+                        self.cancel_authentication(&client_response).await?;
+                        return Ok(Authenticate::Error {
+                            connection: Some(self),
+                            error,
+                        });
                     }
 
-                    let server_challenge = STANDARD.decode(server_response).unwrap();
                     let client_response = sasl.as_mut().resume(server_challenge);
 
                     let client_response = match client_response {
                         Ok(client_response) => client_response,
                         Err(sasl_error) => {
                             // error in SASL, cancel
-                            self.send_command(definitions::sasl_string("*")).await?;
-
-                            let response = next_response(&mut self.stream, response_nobye).await?;
-                            let Response { .. } = handle_bye(&mut self.stream, response).await?;
-
+                            self.cancel_authentication("*").await?;
                             return Ok(Authenticate::Error {
                                 connection: Some(self),
                                 error: SaslError::SaslError(sasl_error),
@@ -94,32 +105,32 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
 
                             if client_finished {
                                 // SASL is already finished, server should not send further challenge
-                                return Ok(Authenticate::Error {
-                                    connection: Some(self),
-                                    error: SaslError::UnexpectedServerResponse,
-                                });
+                                return self
+                                    .abort_authenticated(SaslError::UnexpectedServerResponse)
+                                    .await;
                             }
 
-                            let server_challenge = STANDARD.decode(server_challenge).unwrap();
+                            let Ok(server_challenge) = STANDARD.decode(server_challenge) else {
+                                return self
+                                    .abort_authenticated(SaslError::UnexpectedServerResponse)
+                                    .await;
+                            };
                             let client_response = sasl.resume(server_challenge);
 
                             let client_response = match client_response {
                                 Ok(client_response) => client_response,
                                 Err(sasl_error) => {
-                                    return Ok(Authenticate::Error {
-                                        connection: Some(self),
-                                        error: SaslError::SaslError(sasl_error),
-                                    });
+                                    // e.g. the server could not prove that it knows the password
+                                    return self
+                                        .abort_authenticated(SaslError::SaslError(sasl_error))
+                                        .await;
                                 }
                             };
 
                             if !client_response.has_response() {
                                 break;
                             } else {
-                                return Ok(Authenticate::Error {
-                                    connection: Some(self),
-                                    error: SaslError::UnexpectedOk,
-                                });
+                                return self.abort_authenticated(SaslError::UnexpectedOk).await;
                             }
                         }
                         (Tag::Ok(_), _) => {
@@ -129,10 +140,7 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
                                 break;
                             } else {
                                 // SASL is not considered completed
-                                return Ok(Authenticate::Error {
-                                    connection: None,
-                                    error: SaslError::UnexpectedOk,
-                                });
+                                return self.abort_authenticated(SaslError::UnexpectedOk).await;
                             }
                         }
                         (Tag::No(_), code) => {
@@ -142,6 +150,9 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
                                 Some(ResponseCode::AuthTooWeak) => SaslError::AuthTooWeak,
                                 Some(ResponseCode::EncryptNeeded) => SaslError::EncryptNeeded,
                                 Some(ResponseCode::TransitionNeeded) => SaslError::TransitionNeeded,
+                                Some(ResponseCode::Referral(url)) => {
+                                    SaslError::Referral { url: url.clone() }
+                                }
                                 _ => SaslError::Other {
                                     message: info.human,
                                 },
@@ -156,19 +167,43 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
             }
         }
 
-        self.send_command(definitions::capability).await?;
-        let (capabilities, response) = next_response(&mut self.stream, response_capability).await?;
-        let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
-        if tag.is_no() {
-            return Err(SieveError::UnexpectedNo { info });
-        }
+        let capabilities = self.request_capabilities().await?;
 
         Ok(Authenticate::Ok {
             connection: Connection {
                 stream: self.stream,
-                capabilities: verify_capabilities(capabilities)?,
+                capabilities,
+                last_command: self.last_command,
+                poisoned: false,
+                read_buf: self.read_buf,
+                write_buf: self.write_buf,
+                timeouts: self.timeouts,
+                tls_config: self.tls_config,
+                in_flight: false,
                 _p: Default::default(),
             },
         })
     }
+
+    // The server considers the connection authenticated, but the client does not accept the
+    // outcome, so neither state would match the other.
+    async fn abort_authenticated<E>(
+        mut self,
+        error: SaslError<E>,
+    ) -> Result<Authenticate<E, STREAM, TLS>, SieveError> {
+        self.stream.close().await?;
+        Ok(Authenticate::Error {
+            connection: None,
+            error,
+        })
+    }
+
+    // sends the last client response, after which the server fails the authentication
+    async fn cancel_authentication(&mut self, client_response: &str) -> Result<(), SieveError> {
+        self.send_command(definitions::sasl_string(client_response)).await?;
+
+        let response = self.next_response(response_nobye).await?;
+        let Response { .. } = handle_bye(&mut self.stream, response).await?;
+        Ok(())
+    }
 }
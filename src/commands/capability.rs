@@ -0,0 +1,81 @@
+use crate::capabilities::verify_capabilities;
+use crate::commands::handle_bye;
+use crate::parser::responses::response_capability;
+use crate::parser::Response;
+use crate::state::{AuthMode, TlsMode};
+use crate::{commands, AsyncRead, AsyncWrite, Capabilities, CommandError, Connection, SieveError};
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, MODE: AuthMode>
+    Connection<STREAM, TLS, MODE>
+{
+    pub async fn refresh_capabilities(mut self) -> Result<Self, CommandError<Self>> {
+        let res = self.refresh_capabilities_inner().await;
+        self.recover(res).map(|(connection, ())| connection)
+    }
+
+    pub(crate) async fn refresh_capabilities_inner(&mut self) -> Result<(), SieveError> {
+        self.capabilities = self.request_capabilities().await?;
+        Ok(())
+    }
+
+    pub(crate) async fn request_capabilities(&mut self) -> Result<Capabilities, SieveError> {
+        self.send_command(commands::definitions::capability).await?;
+        self.capabilities_response().await
+    }
+
+    // Reads a capability list, either the response to `CAPABILITY` or one the server sends on its
+    // own.
+    pub(crate) async fn capabilities_response(&mut self) -> Result<Capabilities, SieveError> {
+        let (capabilities, response) = self.next_response(response_capability).await?;
+        let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
+        if tag.is_no() {
+            return Err(SieveError::UnexpectedNo { info });
+        }
+
+        Ok(verify_capabilities(capabilities)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::capabilities::CapabilitiesError;
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    #[test]
+    fn test_refresh_capabilities() {
+        let stream = ScriptedStream::new(
+            &[b"\"IMPLEMENTATION\" \"new\"\r\n\"SIEVE\" \"fileinto\"\r\n\"VERSION\" \"1.0\"\r\nOK\r\n"],
+            1024,
+        );
+
+        let connection =
+            block_on(connection::<Authenticated>(stream).refresh_capabilities()).unwrap();
+        assert_eq!(connection.capabilities().implementation, "new");
+        assert_eq!(connection.capabilities().sieve, ["fileinto"]);
+        assert_eq!(connection.stream.written, b"CAPABILITY\r\n");
+    }
+
+    // the response was read completely, so the connection stays usable with the previous
+    // capabilities
+    #[test]
+    fn test_invalid_capabilities() {
+        let stream = ScriptedStream::new(
+            &[b"\"IMPLEMENTATION\" \"new\"\r\n\"VERSION\" \"1.0\"\r\nOK\r\n"],
+            1024,
+        );
+
+        let error =
+            block_on(connection::<Authenticated>(stream).refresh_capabilities()).unwrap_err();
+        assert!(
+            matches!(error.error, SieveError::CapabilitiesError(CapabilitiesError::MissingSieve)),
+            "{error:?}"
+        );
+        let connection = error.connection.unwrap();
+        assert_eq!(connection.capabilities().implementation, "test");
+        assert!(!connection.stream.closed);
+    }
+}
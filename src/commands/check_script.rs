@@ -1,10 +1,13 @@
 use tracing::warn;
 
-use crate::commands::{handle_bye, next_response};
+use crate::commands::handle_bye;
 use crate::parser::responses::response_oknobye;
 use crate::parser::{Response, Tag};
 use crate::state::{Authenticated, TlsMode};
-use crate::{commands, AsyncRead, AsyncWrite, Connection, ResponseCode, ResponseInfo, SieveError};
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, ResponseInfo,
+    SieveError,
+};
 
 #[derive(Debug)]
 pub enum CheckScript {
@@ -13,10 +16,42 @@ pub enum CheckScript {
 }
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
-    pub async fn check_script(mut self, script: &str) -> Result<(Self, CheckScript), SieveError> {
+    pub async fn check_script(
+        mut self,
+        script: impl AsRef<[u8]>,
+    ) -> Result<(Self, CheckScript), CommandError<Self>> {
+        let res = self.check_script_inner(script.as_ref()).await;
+        self.recover(res)
+    }
+
+    pub async fn check_script_from(
+        mut self,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<(Self, CheckScript), CommandError<Self>> {
+        let res = self.check_script_from_inner(len, script).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn check_script_inner(
+        &mut self,
+        script: &[u8],
+    ) -> Result<CheckScript, SieveError> {
         self.send_command(commands::definitions::check_script(script)).await?;
+        self.check_script_response().await
+    }
+
+    pub(crate) async fn check_script_from_inner(
+        &mut self,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<CheckScript, SieveError> {
+        self.send_command(commands::definitions::check_script_from(len, script)).await?;
+        self.check_script_response().await
+    }
 
-        let response = next_response(&mut self.stream, response_oknobye).await?;
+    async fn check_script_response(&mut self) -> Result<CheckScript, SieveError> {
+        let response = self.next_response(response_oknobye).await?;
         let Response {
             tag,
             info: ResponseInfo { code, human },
@@ -41,6 +76,6 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
             }
         };
 
-        Ok((self, res))
+        Ok(res)
     }
 }
@@ -1,13 +1,92 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_rustls::pki_types::ServerName;
+use futures_rustls::rustls::ClientConfig;
+
 use crate::capabilities::verify_capabilities;
+use crate::commands::start_tls::{platform_config, tls_connect};
 use crate::commands::{handle_bye, next_response};
 use crate::parser::responses::response_capability;
 use crate::parser::Response;
-use crate::state::{NoTls, Unauthenticated};
-use crate::{AsyncRead, AsyncWrite, Connection, SieveError};
+use crate::state::{NoTls, Tls, TlsMode, Unauthenticated};
+use crate::timeout::IdleTimeout;
+use crate::{AsyncRead, AsyncWrite, Connection, SieveError, Timeouts};
+
+/// Settings for establishing a connection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// configuration for the TLS handshake of implicit TLS and `STARTTLS`, the platform verifier
+    /// is used if this is `None`
+    pub tls_config: Option<Arc<ClientConfig>>,
+    pub timeouts: Option<Timeouts>,
+}
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin> Connection<STREAM, NoTls, Unauthenticated> {
-    pub async fn connect(mut stream: STREAM) -> Result<Self, SieveError> {
-        let (capabilities, response) = next_response(&mut stream, response_capability).await?;
+    /// Reads the server greeting of an unencrypted connection, see [`Connection::start_tls`].
+    pub async fn connect(stream: STREAM) -> Result<Self, SieveError> {
+        Self::connect_with_options(stream, ConnectOptions::default()).await
+    }
+
+    pub async fn connect_with_options(
+        stream: STREAM,
+        options: ConnectOptions,
+    ) -> Result<Self, SieveError> {
+        Self::read_capabilities(stream, None, options, |timeouts| timeouts.greeting).await
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin> Connection<STREAM, Tls, Unauthenticated> {
+    /// Performs the TLS handshake before reading the greeting, for servers using implicit TLS
+    /// which do not support `STARTTLS`.
+    pub async fn connect_tls(
+        stream: STREAM,
+        server_name: ServerName<'static>,
+    ) -> Result<Self, SieveError> {
+        Self::connect_tls_with_options(stream, server_name, ConnectOptions::default()).await
+    }
+
+    pub async fn connect_tls_with_options(
+        stream: STREAM,
+        server_name: ServerName<'static>,
+        options: ConnectOptions,
+    ) -> Result<Self, SieveError> {
+        let config = match &options.tls_config {
+            Some(config) => config.clone(),
+            None => platform_config()?,
+        };
+        let stream =
+            tls_connect(stream, server_name, config, options.timeouts.as_ref(), |timeouts| {
+                timeouts.greeting
+            })
+            .await?;
+        Self::read_capabilities(stream, None, options, |timeouts| timeouts.greeting).await
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
+    Connection<STREAM, TLS, Unauthenticated>
+{
+    // Reads the capabilities the server sends after connecting and after `STARTTLS`.
+    pub(crate) async fn read_capabilities(
+        mut stream: TLS::Stream<STREAM>,
+        command: Option<&'static str>,
+        options: ConnectOptions,
+        timeout: fn(&Timeouts) -> Option<Duration>,
+    ) -> Result<Self, SieveError> {
+        let ConnectOptions {
+            tls_config,
+            timeouts,
+        } = options;
+        let mut read_buf = Vec::new();
+        let (capabilities, response) = next_response(
+            &mut stream,
+            &mut read_buf,
+            command,
+            response_capability,
+            IdleTimeout::new(timeouts.as_ref(), timeout),
+        )
+        .await?;
 
         // TODO close connection or send LOGOUT on error?
         let Response { tag, info } = handle_bye(&mut stream, response).await?;
@@ -19,6 +98,13 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin> Connection<STREAM, NoTls, Unauthent
         Ok(Connection {
             stream,
             capabilities: verify_capabilities(capabilities)?,
+            last_command: command,
+            poisoned: false,
+            read_buf,
+            write_buf: Vec::new(),
+            timeouts,
+            tls_config,
+            in_flight: false,
             _p: Default::default(),
         })
     }
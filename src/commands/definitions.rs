@@ -1,55 +1,172 @@
-#![allow(unused)]
-
-use std::future::Future;
 use std::{io, str};
 
-use futures::AsyncWriteExt;
+use futures::{AsyncReadExt, AsyncWriteExt};
 
-use crate::{AsyncRead, AsyncWrite, SieveNameStr};
+use crate::timeout::with_timeout;
+use crate::{AsyncRead, AsyncWrite, Result, SieveError, SieveNameStr, Timeouts};
 
-pub(crate) struct SieveWriter<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
-    pub(crate) &'a mut STREAM,
-);
+pub(crate) struct SieveWriter<'a, STREAM: AsyncRead + AsyncWrite + Unpin> {
+    pub(crate) stream: &'a mut STREAM,
+    // name of the command being written, for diagnostics
+    pub(crate) command: &'a mut Option<&'static str>,
+    // data not yet written to `stream`, so a command goes out in as few writes as possible
+    pub(crate) buf: &'a mut Vec<u8>,
+    pub(crate) timeouts: Option<&'a Timeouts>,
+}
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin> SieveWriter<'_, STREAM> {
-    fn literal<'a>(&'a mut self, s: &'a str) -> impl Future<Output = io::Result<()>> + 'a {
-        self.0.write_all(s.as_bytes())
+    async fn literal(&mut self, s: &'static str) -> io::Result<()> {
+        self.command.get_or_insert(s);
+        self.buf.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    async fn space(&mut self) -> io::Result<()> {
+        self.buf.push(b' ');
+        Ok(())
+    }
+
+    async fn crlf(&mut self) -> io::Result<()> {
+        self.buf.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+
+    async fn string(&mut self, string: impl AsRef<str>) -> Result<()> {
+        self.bytes(string.as_ref().as_bytes()).await
+    }
+
+    async fn bytes(&mut self, string: &[u8]) -> Result<()> {
+        if is_quotable(string) {
+            Ok(self.quoted(string).await?)
+        } else {
+            self.literal_c2s(string).await
+        }
     }
 
-    fn space(&mut self) -> impl Future<Output = io::Result<()>> + '_ {
-        self.0.write_all(b" ")
+    async fn quoted(&mut self, string: &[u8]) -> io::Result<()> {
+        self.buf.push(b'"');
+        for chunk in string.split_inclusive(|&c| c == b'"' || c == b'\\') {
+            let (last, rest) = chunk.split_last().expect("chunks are never empty");
+            if *last == b'"' || *last == b'\\' {
+                self.buf.extend_from_slice(rest);
+                self.buf.extend_from_slice(&[b'\\', *last]);
+            } else {
+                self.buf.extend_from_slice(chunk);
+            }
+        }
+        self.buf.push(b'"');
+        Ok(())
     }
 
-    fn crlf(&mut self) -> impl Future<Output = io::Result<()>> + '_ {
-        self.0.write_all(b"\r\n")
+    // non-synchronizing literal, see `literal-c2s` in section 4 of rfc 5804
+    async fn literal_c2s(&mut self, string: &[u8]) -> Result<()> {
+        let len = literal_len(string.len())?;
+        self.literal_prefix(len).await?;
+        if string.len() > MAX_BUFFERED_LEN {
+            self.write_buf().await?;
+            write_all(self.stream, self.timeouts, string).await?;
+        } else {
+            self.buf.extend_from_slice(string);
+        }
+
+        Ok(())
     }
 
-    async fn string(&mut self, string: impl AsRef<str>) -> io::Result<()> {
-        let string = string.as_ref();
+    // Errors of `reader` are only recoverable as long as the literal is buffered, once a part of it
+    // was sent the connection is unusable.
+    async fn literal_from(&mut self, len: u32, reader: impl AsyncRead + Unpin) -> Result<()> {
+        self.literal_prefix(len).await?;
 
-        self.0.write_all(b"{").await?;
-        self.number(string.len().try_into().unwrap()).await?;
-        self.0.write_all(b"}").await?;
-        self.crlf().await?;
-        self.0.write_all(string.as_bytes()).await?;
+        let mut reader = reader.take(len.into());
+        let short_read = || {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "reader ended before the announced length")
+        };
+        if len as usize > MAX_BUFFERED_LEN {
+            self.write_buf().await?;
+
+            // the caller's reader is not subject to the timeout, only the writes to the server
+            let mut chunk = vec![0; MAX_BUFFERED_LEN];
+            let mut copied = 0;
+            loop {
+                let read_count = reader.read(&mut chunk).await?;
+                if read_count == 0 {
+                    break;
+                }
+                write_all(self.stream, self.timeouts, &chunk[..read_count]).await?;
+                copied += read_count as u64;
+            }
+            if copied != u64::from(len) {
+                return Err(short_read().into());
+            }
+        } else {
+            let copied = reader.read_to_end(self.buf).await.map_err(SieveError::Source)?;
+            if copied != len as usize {
+                return Err(SieveError::Source(short_read()));
+            }
+        }
 
         Ok(())
     }
 
+    async fn literal_prefix(&mut self, len: u32) -> io::Result<()> {
+        self.buf.push(b'{');
+        self.number(len).await?;
+        self.buf.extend_from_slice(b"+}");
+        self.crlf().await
+    }
+
     async fn number(&mut self, number: u32) -> io::Result<()> {
         let mut buffer = itoa::Buffer::new();
-        self.0.write_all(buffer.format(number).as_bytes()).await?;
+        self.buf.extend_from_slice(buffer.format(number).as_bytes());
+        Ok(())
+    }
+
+    async fn write_buf(&mut self) -> Result<()> {
+        write_all(self.stream, self.timeouts, self.buf).await?;
+        self.buf.clear();
         Ok(())
     }
 }
 
+// Writes `data` in chunks, each of which must be written within the command timeout.
+pub(crate) async fn write_all(
+    stream: &mut (impl AsyncWrite + Unpin),
+    timeouts: Option<&Timeouts>,
+    data: &[u8],
+) -> Result<()> {
+    for chunk in data.chunks(MAX_BUFFERED_LEN) {
+        with_timeout(timeouts, |timeouts| timeouts.command, async {
+            Ok(stream.write_all(chunk).await?)
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+// literals larger than this are written directly instead of being copied into the buffer
+const MAX_BUFFERED_LEN: usize = 16 * 1024;
+
+const MAX_QUOTED_LEN: usize = 1024;
+
+// the length of a literal must fit into a `number`, see section 4 of rfc 5804
+fn literal_len(len: usize) -> Result<u32> {
+    len.try_into().map_err(|_| SieveError::LiteralTooLong { len })
+}
+
+// see `quoted` in section 4 of rfc 5804
+fn is_quotable(string: &[u8]) -> bool {
+    string.len() <= MAX_QUOTED_LEN
+        && !string.iter().any(|c| matches!(c, b'\0' | b'\r' | b'\n'))
+        && str::from_utf8(string).is_ok()
+}
+
 pub(crate) trait Command<'a, STREAM: AsyncRead + AsyncWrite + Unpin>:
-    AsyncFn(SieveWriter<STREAM>) -> io::Result<()> + 'a
+    AsyncFnOnce(SieveWriter<STREAM>) -> Result<()> + 'a
 {
 }
 
 impl<'a, STREAM: AsyncRead + AsyncWrite + Unpin, T: 'a> Command<'a, STREAM> for T where
-    T: AsyncFn(SieveWriter<STREAM>) -> io::Result<()>
+    T: AsyncFnOnce(SieveWriter<STREAM>) -> Result<()>
 {
 }
 
@@ -82,7 +199,7 @@ pub(crate) fn sasl_string<STREAM: AsyncRead + AsyncWrite + Unpin>(
 
 pub(crate) async fn start_tls<STREAM: AsyncRead + AsyncWrite + Unpin>(
     mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
+) -> Result<()> {
     write.literal("STARTTLS").await?;
     write.crlf().await?;
     Ok(())
@@ -90,7 +207,7 @@ pub(crate) async fn start_tls<STREAM: AsyncRead + AsyncWrite + Unpin>(
 
 pub(crate) async fn logout<STREAM: AsyncRead + AsyncWrite + Unpin>(
     mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
+) -> Result<()> {
     write.literal("LOGOUT").await?;
     write.crlf().await?;
     Ok(())
@@ -98,7 +215,7 @@ pub(crate) async fn logout<STREAM: AsyncRead + AsyncWrite + Unpin>(
 
 pub(crate) async fn capability<STREAM: AsyncRead + AsyncWrite + Unpin>(
     mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
+) -> Result<()> {
     write.literal("CAPABILITY").await?;
     write.crlf().await?;
     Ok(())
@@ -121,14 +238,30 @@ pub(crate) fn have_space<STREAM: AsyncRead + AsyncWrite + Unpin>(
 
 pub(crate) fn put_script<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
     name: &'a SieveNameStr,
-    script: &'a str,
+    script: &'a [u8],
 ) -> impl Command<'a, STREAM> {
     async move |mut write: SieveWriter<STREAM>| {
         write.literal("PUTSCRIPT").await?;
         write.space().await?;
         write.string(name).await?;
         write.space().await?;
-        write.string(script).await?;
+        write.bytes(script).await?;
+        write.crlf().await?;
+        Ok(())
+    }
+}
+
+pub(crate) fn put_script_from<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
+    name: &'a SieveNameStr,
+    len: u32,
+    reader: impl AsyncRead + Unpin + 'a,
+) -> impl Command<'a, STREAM> {
+    async move |mut write: SieveWriter<STREAM>| {
+        write.literal("PUTSCRIPT").await?;
+        write.space().await?;
+        write.string(name).await?;
+        write.space().await?;
+        write.literal_from(len, reader).await?;
         write.crlf().await?;
         Ok(())
     }
@@ -136,7 +269,7 @@ pub(crate) fn put_script<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
 
 pub(crate) async fn list_scripts<STREAM: AsyncRead + AsyncWrite + Unpin>(
     mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
+) -> Result<()> {
     write.literal("LISTSCRIPTS").await?;
     write.crlf().await?;
     Ok(())
@@ -194,29 +327,194 @@ pub(crate) fn rename_script<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
 }
 
 pub(crate) fn check_script<STREAM: AsyncRead + AsyncWrite + Unpin>(
-    script: &str,
+    script: &[u8],
 ) -> impl Command<'_, STREAM> {
     async move |mut write: SieveWriter<STREAM>| {
         write.literal("CHECKSCRIPT").await?;
         write.space().await?;
-        write.string(script).await?;
+        write.bytes(script).await?;
         write.crlf().await?;
         Ok(())
     }
 }
 
-pub(crate) async fn noop<STREAM: AsyncRead + AsyncWrite + Unpin>(
-    mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
-    write.literal("NOOP").await?;
-    write.crlf().await?;
-    Ok(())
+pub(crate) fn check_script_from<'a, STREAM: AsyncRead + AsyncWrite + Unpin>(
+    len: u32,
+    reader: impl AsyncRead + Unpin + 'a,
+) -> impl Command<'a, STREAM> {
+    async move |mut write: SieveWriter<STREAM>| {
+        write.literal("CHECKSCRIPT").await?;
+        write.space().await?;
+        write.literal_from(len, reader).await?;
+        write.crlf().await?;
+        Ok(())
+    }
+}
+
+pub(crate) fn noop<STREAM: AsyncRead + AsyncWrite + Unpin>(
+    tag: Option<&str>,
+) -> impl Command<'_, STREAM> {
+    async move |mut write: SieveWriter<STREAM>| {
+        write.literal("NOOP").await?;
+        if let Some(tag) = tag {
+            write.space().await?;
+            write.string(tag).await?;
+        }
+        write.crlf().await?;
+        Ok(())
+    }
 }
 
 pub(crate) async fn unauthenticate<STREAM: AsyncRead + AsyncWrite + Unpin>(
     mut write: SieveWriter<'_, STREAM>,
-) -> io::Result<()> {
+) -> Result<()> {
     write.literal("UNAUTHENTICATE").await?;
     write.crlf().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    fn check_script_written(script: &[u8]) -> Vec<u8> {
+        let mut connection = connection::<Authenticated>(ScriptedStream::new(&[], usize::MAX));
+        block_on(connection.send_command(check_script(script))).unwrap();
+        connection.stream.written
+    }
+
+    fn literal(script: &[u8]) -> Vec<u8> {
+        [
+            format!("CHECKSCRIPT {{{}+}}\r\n", script.len()).as_bytes(),
+            script,
+            b"\r\n",
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_quoted_escaping() {
+        assert_eq!(check_script_written(b"keep;"), b"CHECKSCRIPT \"keep;\"\r\n");
+        assert_eq!(check_script_written(b""), b"CHECKSCRIPT \"\"\r\n");
+        assert_eq!(
+            check_script_written(br#"a "b" \c\"#),
+            b"CHECKSCRIPT \"a \\\"b\\\" \\\\c\\\\\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_quoted_length_limit() {
+        let script = vec![b'a'; MAX_QUOTED_LEN];
+        let quoted = [b"CHECKSCRIPT \"".as_slice(), &script, b"\"\r\n"].concat();
+        assert_eq!(check_script_written(&script), quoted);
+
+        let script = vec![b'a'; MAX_QUOTED_LEN + 1];
+        assert_eq!(check_script_written(&script), literal(&script));
+    }
+
+    #[test]
+    fn test_unquotable() {
+        for script in [
+            &b"a\0b"[..],
+            b"keep;\r\nstop;",
+            b"keep;\rstop;",
+            b"keep;\nstop;",
+            b"\xff",
+        ] {
+            assert_eq!(check_script_written(script), literal(script), "{}", script.escape_ascii());
+        }
+    }
+
+    #[test]
+    fn test_non_synchronizing_literal() {
+        assert_eq!(
+            check_script_written(b"keep;\r\nstop;"),
+            b"CHECKSCRIPT {12+}\r\nkeep;\r\nstop;\r\n"
+        );
+
+        // large literals bypass the buffer
+        let script = "keep;\r\n".repeat(MAX_BUFFERED_LEN);
+        assert_eq!(check_script_written(script.as_bytes()), literal(script.as_bytes()));
+    }
+
+    #[test]
+    fn test_literal_len() {
+        assert_eq!(literal_len(12).unwrap(), 12);
+        assert_eq!(literal_len(u32::MAX as usize).unwrap(), u32::MAX);
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            literal_len(u32::MAX as usize + 1),
+            Err(SieveError::LiteralTooLong { len }) if len == u32::MAX as usize + 1
+        ));
+    }
+
+    #[test]
+    fn test_failed_command_is_not_queued() {
+        let mut connection = connection::<Authenticated>(ScriptedStream::new(&[], usize::MAX));
+        block_on(connection.queue_command(capability)).unwrap();
+        let res = block_on(connection.queue_command(async |mut write: SieveWriter<_>| {
+            write.literal("CHECKSCRIPT").await?;
+            write.space().await?;
+            Err(SieveError::LiteralTooLong { len: 0 })
+        }));
+        assert!(res.unwrap_err().is_recoverable());
+
+        block_on(connection.flush_commands()).unwrap();
+        assert_eq!(connection.stream.written, b"CAPABILITY\r\n");
+        assert!(!connection.stream.closed);
+    }
+
+    struct FailingReader;
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+        }
+    }
+
+    #[test]
+    fn test_failing_reader() {
+        let connection = connection::<Authenticated>(ScriptedStream::new(&[], usize::MAX));
+        let error = block_on(connection.check_script_from(5, FailingReader)).unwrap_err();
+        assert!(
+            matches!(&error.error, SieveError::Source(error) if error.kind() == io::ErrorKind::PermissionDenied)
+        );
+
+        // nothing was sent, so the connection is still usable
+        let connection = error.connection.unwrap();
+        assert!(connection.stream.written.is_empty());
+        assert!(connection.write_buf.is_empty());
+        assert!(!connection.stream.closed);
+    }
+
+    #[test]
+    fn test_short_reader() {
+        let new_connection = || connection::<Authenticated>(ScriptedStream::new(&[], usize::MAX));
+        let error = block_on(new_connection().check_script_from(6, &b"keep;"[..])).unwrap_err();
+        assert!(
+            matches!(&error.error, SieveError::Source(error) if error.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        assert!(error.connection.unwrap().stream.written.is_empty());
+
+        // a part of a large literal was already sent
+        let script = vec![b'a'; MAX_BUFFERED_LEN + 1];
+        let error =
+            block_on(new_connection().check_script_from(script.len() as u32 + 1, &script[..]))
+                .unwrap_err();
+        assert!(
+            matches!(&error.error, SieveError::Io(error) if error.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        assert!(error.connection.is_none());
+    }
+}
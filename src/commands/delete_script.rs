@@ -0,0 +1,102 @@
+use tracing::warn;
+
+use crate::commands::handle_bye;
+use crate::parser::responses::response_oknobye;
+use crate::parser::{Response, Tag};
+use crate::state::{Authenticated, TlsMode};
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, ResponseInfo, Result,
+    SieveError, SieveNameStr,
+};
+
+#[derive(Debug)]
+pub enum DeleteScript {
+    Ok,
+    Active { message: Option<String> },
+    Nonexistent { message: Option<String> },
+    Other { message: Option<String> },
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    pub async fn delete_script(
+        mut self,
+        name: &SieveNameStr,
+    ) -> Result<(Self, DeleteScript), CommandError<Self>> {
+        let res = self.delete_script_inner(name).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn delete_script_inner(
+        &mut self,
+        name: &SieveNameStr,
+    ) -> Result<DeleteScript, SieveError> {
+        self.send_command(commands::definitions::delete_script(name)).await?;
+        self.delete_script_response().await
+    }
+
+    pub(crate) async fn delete_script_response(&mut self) -> Result<DeleteScript, SieveError> {
+        let response = self.next_response(response_oknobye).await?;
+        let Response {
+            tag,
+            info: ResponseInfo { code, human },
+        } = handle_bye(&mut self.stream, response).await?;
+
+        let res = match tag {
+            Tag::Ok(_) => DeleteScript::Ok,
+            Tag::No(_) => match code {
+                Some(ResponseCode::Active) => DeleteScript::Active { message: human },
+                Some(ResponseCode::Nonexistent) => DeleteScript::Nonexistent { message: human },
+                code => {
+                    if let Some(code) = code {
+                        warn!("unexpected response code `{code}` in `NO` reply from `DELETESCRIPT` command");
+                    }
+                    DeleteScript::Other { message: human }
+                }
+            },
+        };
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_util::{connection, ScriptedStream};
+
+    fn delete(response: &[u8]) -> DeleteScript {
+        let name = SieveNameStr::new(&"vacation").unwrap();
+        let stream = ScriptedStream::new(&[response], 1024);
+
+        let (connection, res) =
+            block_on(connection::<Authenticated>(stream).delete_script(name)).unwrap();
+        assert_eq!(connection.stream.written, b"DELETESCRIPT \"vacation\"\r\n");
+        res
+    }
+
+    #[test]
+    fn test_delete() {
+        let res = delete(b"OK\r\n");
+        assert!(matches!(res, DeleteScript::Ok), "{res:?}");
+    }
+
+    #[test]
+    fn test_active() {
+        let res = delete(b"NO (ACTIVE) \"script is active\"\r\n");
+        assert!(
+            matches!(&res, DeleteScript::Active { message } if message.as_deref() == Some("script is active")),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn test_nonexistent() {
+        let res = delete(b"NO (NONEXISTENT) \"no such script\"\r\n");
+        assert!(
+            matches!(&res, DeleteScript::Nonexistent { message } if message.as_deref() == Some("no such script")),
+            "{res:?}"
+        );
+    }
+}
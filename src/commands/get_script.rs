@@ -1,20 +1,49 @@
+use std::io;
+
 use either::Either;
+use futures::{AsyncReadExt, AsyncWriteExt};
 use tracing::warn;
 
-use crate::commands::{handle_bye, next_response};
-use crate::parser::responses::response_getscript;
+use crate::commands::handle_bye;
+use crate::parser::responses::{
+    response_getscript, response_getscript_end, response_getscript_start,
+};
 use crate::parser::Response;
 use crate::state::{Authenticated, TlsMode};
-use crate::{commands, AsyncRead, AsyncWrite, Connection, ResponseCode, Result, SieveNameStr};
+use crate::timeout::with_timeout;
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, Result, Script,
+    SieveError, SieveNameStr,
+};
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
-    pub async fn get_script(mut self, name: &SieveNameStr) -> Result<(Self, Option<String>)> {
+    pub async fn get_script(
+        mut self,
+        name: &SieveNameStr,
+    ) -> Result<(Self, Option<Script>), CommandError<Self>> {
+        let res = self.get_script_inner(name).await;
+        self.recover(res)
+    }
+
+    pub async fn get_script_to(
+        mut self,
+        name: &SieveNameStr,
+        sink: impl AsyncWrite + Unpin,
+    ) -> Result<(Self, Option<u64>), CommandError<Self>> {
+        let res = self.get_script_to_inner(name, sink).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn get_script_inner(&mut self, name: &SieveNameStr) -> Result<Option<Script>> {
         self.send_command(commands::definitions::get_script(name)).await?;
+        self.get_script_response().await
+    }
 
-        let response = next_response(&mut self.stream, response_getscript).await?;
+    pub(crate) async fn get_script_response(&mut self) -> Result<Option<Script>> {
+        let response = self.next_response(response_getscript).await?;
 
         let res = match response {
-            Either::Left((script, _)) => Some(script),
+            Either::Left((script, _)) => Some(Script::from(script)),
             Either::Right(response) => {
                 let Response { info, .. } = handle_bye(&mut self.stream, response).await?;
 
@@ -26,6 +55,78 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
             }
         };
 
-        Ok((self, res))
+        Ok(res)
+    }
+
+    pub(crate) async fn get_script_to_inner(
+        &mut self,
+        name: &SieveNameStr,
+        mut sink: impl AsyncWrite + Unpin,
+    ) -> Result<Option<u64>> {
+        self.send_command(commands::definitions::get_script(name)).await?;
+
+        let response = self.next_response(response_getscript_start).await?;
+
+        let len = match response {
+            Either::Left(Either::Left(len)) => {
+                let res = self.copy_literal(len, &mut sink).await;
+                if res.is_err() {
+                    self.stream.close().await?;
+                }
+                let sink_res = res?;
+
+                self.next_response(response_getscript_end).await?;
+                sink_res.map_err(SieveError::Sink)?;
+                len
+            }
+            Either::Left(Either::Right((script, _))) => {
+                sink.write_all(&script).await.map_err(SieveError::Sink)?;
+                script.len() as u64
+            }
+            Either::Right(response) => {
+                let Response { info, .. } = handle_bye(&mut self.stream, response).await?;
+
+                if info.code != Some(ResponseCode::Nonexistent) {
+                    warn!("`NO` reply from `GETSCRIPT` command is missing `NONEXISTENT` response code");
+                }
+
+                return Ok(None);
+            }
+        };
+        sink.flush().await.map_err(SieveError::Sink)?;
+
+        Ok(Some(len))
+    }
+
+    // Errors of `sink` are returned separately. The rest of the literal is still read after such an
+    // error, so the connection remains usable.
+    async fn copy_literal(
+        &mut self,
+        len: u64,
+        sink: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<io::Result<()>> {
+        let buffered = self.read_buf.len().min(len.try_into().unwrap_or(usize::MAX));
+        let mut sink_res = sink.write_all(&self.read_buf[..buffered]).await;
+        self.read_buf.drain(..buffered);
+
+        let mut remaining = len - buffered as u64;
+        let mut temp = [0u8; 8192];
+        while remaining > 0 {
+            let max = temp.len().min(remaining.try_into().unwrap_or(usize::MAX));
+            let read_count =
+                with_timeout(self.timeouts.as_ref(), |timeouts| timeouts.command, async {
+                    Ok(self.stream.read(&mut temp[..max]).await?)
+                })
+                .await?;
+            if read_count == 0 {
+                return Err(SieveError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            if sink_res.is_ok() {
+                sink_res = sink.write_all(&temp[..read_count]).await;
+            }
+            remaining -= read_count as u64;
+        }
+
+        Ok(sink_res)
     }
 }
@@ -2,13 +2,13 @@ use std::fmt::Debug;
 
 use tracing::warn;
 
-use crate::commands::{handle_bye, next_response};
+use crate::commands::handle_bye;
 use crate::parser::responses::response_oknobye;
 use crate::parser::{Response, Tag};
 use crate::state::{Authenticated, TlsMode};
 use crate::{
-    commands, AsyncRead, AsyncWrite, Connection, Quota, ResponseCode, Result, SieveError,
-    SieveNameStr,
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, Quota, ResponseCode, Result,
+    SieveError, SieveNameStr,
 };
 
 #[derive(Debug)]
@@ -25,10 +25,22 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
         mut self,
         name: &SieveNameStr,
         size: u32,
-    ) -> Result<(Self, HaveSpace), SieveError> {
+    ) -> Result<(Self, HaveSpace), CommandError<Self>> {
+        let res = self.have_space_inner(name, size).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn have_space_inner(
+        &mut self,
+        name: &SieveNameStr,
+        size: u32,
+    ) -> Result<HaveSpace, SieveError> {
         self.send_command(commands::definitions::have_space(name, size)).await?;
+        self.have_space_response().await
+    }
 
-        let response = next_response(&mut self.stream, response_oknobye).await?;
+    pub(crate) async fn have_space_response(&mut self) -> Result<HaveSpace, SieveError> {
+        let response = self.next_response(response_oknobye).await?;
         let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
 
         let res = match tag {
@@ -50,6 +62,6 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
             }
         };
 
-        Ok((self, res))
+        Ok(res)
     }
 }
@@ -1,22 +1,32 @@
-use crate::commands::{handle_bye, next_response};
+use crate::commands::handle_bye;
 use crate::parser::responses::response_listscripts;
 use crate::parser::Response;
 use crate::state::{Authenticated, TlsMode};
-use crate::{commands, AsyncRead, AsyncWrite, Connection, SieveError, SieveNameString};
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, SieveError, SieveNameString,
+};
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    #[allow(clippy::type_complexity)]
     pub async fn list_scripts(
         mut self,
-    ) -> Result<(Self, Vec<(SieveNameString, bool)>), SieveError> {
+    ) -> Result<(Self, Vec<(SieveNameString, bool)>), CommandError<Self>> {
+        let res = self.list_scripts_inner().await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn list_scripts_inner(
+        &mut self,
+    ) -> Result<Vec<(SieveNameString, bool)>, SieveError> {
         self.send_command(commands::definitions::list_scripts).await?;
 
-        let (scripts, response) = next_response(&mut self.stream, response_listscripts).await?;
+        let (scripts, response) = self.next_response(response_listscripts).await?;
         let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
 
         if tag.is_no() {
             return Err(SieveError::UnexpectedNo { info });
         }
 
-        Ok((self, scripts))
+        Ok(scripts)
     }
 }
@@ -1,6 +1,6 @@
 use futures::AsyncWriteExt;
 
-use crate::commands::{handle_bye, next_response};
+use crate::commands::handle_bye;
 use crate::parser::responses::response_oknobye;
 use crate::parser::{Response, Tag};
 use crate::state::{AuthMode, TlsMode};
@@ -12,7 +12,7 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, MODE: AuthMode>
     pub async fn logout(mut self) -> Result<(), SieveError> {
         self.send_command(commands::definitions::logout).await?;
 
-        let response = next_response(&mut self.stream, response_oknobye).await?;
+        let response = self.next_response(response_oknobye).await?;
         let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
 
         match tag {
@@ -1,13 +1,21 @@
 mod authenticate;
+mod capability;
 mod check_script;
 mod connect;
 mod definitions;
+mod delete_script;
 mod get_script;
 mod have_space;
 mod list_scripts;
 mod logout;
+mod noop;
+mod pipeline;
 mod put_script;
-mod start_tls;
+mod referral;
+mod rename_script;
+mod set_active;
+pub(crate) mod start_tls;
+mod unauthenticate;
 
 use std::convert::Infallible;
 use std::fmt::Debug;
@@ -16,21 +24,28 @@ use std::io;
 use std::pin::Pin;
 use std::task::{ready, Poll};
 
-use definitions::{Command, SieveWriter};
+use definitions::{write_all, Command, SieveWriter};
 use futures::AsyncWriteExt;
 use tracing::{debug, warn};
-use winnow::combinator::{eof, terminated};
-use winnow::error::ErrMode;
-use winnow::{BStr, ModalResult as PResult, Parser, Partial};
+use winnow::error::{ErrMode, Needed, StrContext};
+use winnow::{BStr, ModalResult as PResult, Partial};
 
 pub use self::authenticate::*;
 pub use self::check_script::*;
+pub use self::connect::*;
+pub use self::delete_script::*;
 pub use self::have_space::*;
+pub use self::pipeline::*;
 pub use self::put_script::*;
+pub use self::referral::*;
+pub use self::rename_script::*;
+pub use self::set_active::*;
+pub use self::unauthenticate::*;
 use crate::parser::responses::Input;
 use crate::parser::{tag, tag_trait, Response, Tag};
 use crate::state::{AuthMode, TlsMode};
-use crate::{AsyncRead, AsyncWrite, Connection, SieveError};
+use crate::timeout::{with_timeout, IdleTimeout};
+use crate::{AsyncRead, AsyncWrite, CommandError, Connection, ParseError, SieveError};
 
 impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, AUTH: AuthMode>
     Connection<STREAM, TLS, AUTH>
@@ -40,21 +55,96 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, AUTH: AuthMode>
         &mut self,
         command: impl Command<'_, <TLS as TlsMode>::Stream<STREAM>>,
     ) -> Result<(), SieveError> {
-        let res: Result<(), SieveError> = async {
-            let writer = SieveWriter(&mut self.stream);
-            command(writer).await?;
-            self.stream.flush().await?;
-            Ok(())
+        self.queue_command(command).await?;
+        self.flush_commands().await
+    }
+
+    // Buffers a command without sending it, so several commands can be sent at once.
+    // Large literals are still written to the stream directly.
+    pub(crate) async fn queue_command(
+        &mut self,
+        command: impl Command<'_, <TLS as TlsMode>::Stream<STREAM>>,
+    ) -> Result<(), SieveError> {
+        if self.poisoned {
+            return Err(SieveError::Poisoned);
+        }
+
+        self.last_command = None;
+        let queued = self.write_buf.len();
+        let writer = SieveWriter {
+            stream: &mut self.stream,
+            command: &mut self.last_command,
+            buf: &mut self.write_buf,
+            timeouts: self.timeouts.as_ref(),
+        };
+        let res = command(writer).await;
+
+        match res {
+            Err(SieveError::Io(_) | SieveError::Timeout) => self.stream.close().await?,
+            // other errors occur before any part of the command is sent
+            Err(_) => self.write_buf.truncate(queued),
+            Ok(()) => {}
+        }
+        res
+    }
+
+    pub(crate) async fn flush_commands(&mut self) -> Result<(), SieveError> {
+        let timeouts = self.timeouts.as_ref();
+        let res = async {
+            write_all(&mut self.stream, timeouts, &self.write_buf).await?;
+            self.write_buf.clear();
+            with_timeout(timeouts, |timeouts| timeouts.command, async {
+                Ok(self.stream.flush().await?)
+            })
+            .await
         }
         .await;
 
-        if let Err(err) = res {
+        if let Err(SieveError::Io(_) | SieveError::Timeout) = res {
             self.stream.close().await?;
-            Err(err)
-        } else {
-            Ok(())
+        }
+        res
+    }
+
+    pub(crate) async fn next_response<RES: 'static + Debug>(
+        &mut self,
+        parser: fn(Input) -> PResult<RES>,
+    ) -> Result<RES, SieveError> {
+        let timeout = IdleTimeout::new(self.timeouts.as_ref(), |timeouts| timeouts.command);
+        next_response(&mut self.stream, &mut self.read_buf, self.last_command, parser, timeout)
+            .await
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, AUTH: AuthMode>
+    Connection<STREAM, TLS, AUTH>
+{
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn recover<T>(
+        self,
+        res: Result<T, SieveError>,
+    ) -> Result<(Self, T), CommandError<Self>> {
+        match res {
+            Ok(value) => Ok((self, value)),
+            Err(error) if error.is_recoverable() => Err(CommandError {
+                connection: Some(self),
+                error,
+            }),
+            Err(error) => Err(error.into()),
         }
     }
+
+    pub(crate) fn poison_on_fatal<T>(
+        &mut self,
+        res: Result<T, SieveError>,
+    ) -> Result<T, SieveError> {
+        if let Err(error) = &res {
+            if !error.is_recoverable() {
+                self.poisoned = true;
+            }
+        }
+        res
+    }
 }
 
 pub(crate) async fn handle_bye<OK: tag_trait::Ok, NO: tag_trait::No, STREAM: AsyncWrite + Unpin>(
@@ -77,11 +167,17 @@ pub(crate) async fn handle_bye<OK: tag_trait::Ok, NO: tag_trait::No, STREAM: Asy
     }
 }
 
+// Parses a response from the start of `buf`, reading more data from `stream` as needed.
+// Any data following the response is left in `buf` for the next response.
+// `timeout` limits the time each read from `stream` may take.
 pub(crate) async fn next_response<STREAM: AsyncRead + AsyncWrite + Unpin, RES: 'static + Debug>(
     stream: &mut STREAM,
+    buf: &mut Vec<u8>,
+    command: Option<&'static str>,
     parser: fn(Input) -> PResult<RES>,
+    timeout: Option<IdleTimeout>,
 ) -> Result<RES, SieveError> {
-    let res = next_response_inner(stream, parser).await;
+    let res = next_response_inner(stream, buf, command, parser, timeout).await;
     debug!(?res);
     if res.is_err() {
         stream.close().await?;
@@ -89,32 +185,179 @@ pub(crate) async fn next_response<STREAM: AsyncRead + AsyncWrite + Unpin, RES: '
     res
 }
 
-pub(crate) fn next_response_inner<STREAM: AsyncRead + Unpin, RES: 'static>(
-    stream: &mut STREAM,
+const READ_SIZE: usize = 8192;
+
+pub(crate) fn next_response_inner<'a, STREAM: AsyncRead + Unpin, RES: 'static>(
+    stream: &'a mut STREAM,
+    buf: &'a mut Vec<u8>,
+    command: Option<&'static str>,
     parser: fn(Input) -> PResult<RES>,
-) -> impl Future<Output = Result<RES, SieveError>> + '_ {
-    let mut buf = Vec::new();
+    mut timeout: Option<IdleTimeout>,
+) -> impl Future<Output = Result<RES, SieveError>> + 'a {
     let mut pin = Pin::new(stream);
+    let mut read = buf.is_empty();
+    // minimum length of `buf` before the parser can make progress
+    let mut needed = 0;
 
     std::future::poll_fn::<Result<RES, SieveError>, _>(move |cx| loop {
-        let mut temp = [0u8; 1024];
-        let read_count = ready!(pin.as_mut().poll_read(cx, &mut temp))?;
+        if read {
+            let len = buf.len();
+            buf.resize(len + READ_SIZE, 0);
+            let res = pin.as_mut().poll_read(cx, &mut buf[len..]);
+            let read_count = match res {
+                Poll::Ready(Ok(read_count)) => read_count,
+                _ => 0,
+            };
+            buf.truncate(len + read_count);
+            if res.is_pending()
+                && timeout.as_mut().is_some_and(|timeout| timeout.poll_elapsed(cx).is_ready())
+            {
+                return Poll::Ready(Err(SieveError::Timeout));
+            }
+            ready!(res)?;
+            if let Some(timeout) = &mut timeout {
+                timeout.reset();
+            }
 
-        if read_count == 0 {
-            return Poll::Ready(Err(SieveError::Io(io::Error::from(io::ErrorKind::UnexpectedEof))));
-        }
+            if read_count == 0 {
+                return Poll::Ready(Err(SieveError::Io(io::Error::from(
+                    io::ErrorKind::UnexpectedEof,
+                ))));
+            }
 
-        buf.extend_from_slice(&temp[0..read_count]);
+            // every response ends with a line break, so the parser cannot succeed before one
+            // arrived; a syntax error will still be noticed with the next line
+            if buf.len() < needed || !buf[len..].contains(&b'\n') {
+                continue;
+            }
+        }
+        read = true;
 
-        let mut partial = Partial::new(BStr::new(&buf));
-        match terminated(parser, eof).parse_next(&mut partial) {
-            Err(ErrMode::Incomplete(_)) => continue,
-            Ok(res) => return Poll::Ready(Ok(res)),
+        let mut partial = Partial::new(BStr::new(buf.as_slice()));
+        match parser(&mut partial) {
+            Err(ErrMode::Incomplete(Needed::Size(size))) => {
+                needed = buf.len() + size.get();
+                continue;
+            }
+            Err(ErrMode::Incomplete(Needed::Unknown)) => continue,
+            Ok(res) => {
+                let consumed = buf.len() - partial.len();
+                buf.drain(..consumed);
+                return Poll::Ready(Ok(res));
+            }
             Err(err) => {
                 warn!(?err);
-                // TODO improve parser error handling
-                return Poll::Ready(Err(SieveError::Syntax));
+                let offset = buf.len() - partial.len();
+                let expected = err
+                    .into_inner()
+                    .ok()
+                    .and_then(|err| {
+                        // labels are pushed while unwinding, the last one is the outermost parser
+                        err.context()
+                            .filter_map(|context| match context {
+                                StrContext::Label(label) => Some(*label),
+                                _ => None,
+                            })
+                            .last()
+                    })
+                    .unwrap_or("response");
+                return Poll::Ready(Err(SieveError::Syntax(ParseError::new(
+                    command, expected, buf, offset,
+                ))));
             }
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::parser::responses::{response_getscript, response_oknobye};
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+    use crate::SieveNameStr;
+
+    fn get_script(stream: &mut ScriptedStream, buf: &mut Vec<u8>) -> Vec<u8> {
+        let response = block_on(next_response(stream, buf, None, response_getscript, None));
+        match response.unwrap() {
+            Either::Left((script, _)) => script,
+            Either::Right(response) => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn test_one_byte_reads() {
+        let mut stream = ScriptedStream::new(&[b"{12}\r\nkeep;\r\nstop;\r\nOK\r\n"], 1);
+        let mut buf = Vec::new();
+        assert_eq!(get_script(&mut stream, &mut buf), b"keep;\r\nstop;");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_large_reads() {
+        let script = "keep;\r\n".repeat(10_000);
+        let response = format!("{{{}}}\r\n{script}\r\nOK\r\n", script.len());
+        let mut stream = ScriptedStream::new(&[response.as_bytes()], usize::MAX);
+        let mut buf = Vec::new();
+        assert_eq!(get_script(&mut stream, &mut buf), script.as_bytes());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_literal_split_across_reads() {
+        let mut stream =
+            ScriptedStream::new(&[b"{12}\r\nkee", b"p;\r", b"\nstop;", b"\r\nOK\r\n"], usize::MAX);
+        let mut buf = Vec::new();
+        assert_eq!(get_script(&mut stream, &mut buf), b"keep;\r\nstop;");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_several_responses_in_one_read() {
+        let mut stream =
+            ScriptedStream::new(&[b"OK\r\nNO \"failed\"\r\n\"keep;\"\r\nOK\r\nOK"], usize::MAX);
+        let mut buf = Vec::new();
+
+        let response = block_on(next_response(&mut stream, &mut buf, None, response_oknobye, None));
+        assert!(matches!(response.unwrap().tag, Tag::Ok(_)));
+        let response = block_on(next_response(&mut stream, &mut buf, None, response_oknobye, None));
+        assert!(response.unwrap().tag.is_no());
+        assert_eq!(get_script(&mut stream, &mut buf), b"keep;");
+
+        // the start of the next response is kept
+        assert_eq!(buf, b"OK");
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let stream = ScriptedStream::new(
+            &[b"{5}\r\nkeep;\r\nOK\r\nNO (QUOTA) \"too big\"\r\nOK\r\nNO (NONEXISTENT) \"missing\"\r\n"],
+            usize::MAX,
+        );
+        let connection = connection::<Authenticated>(stream);
+
+        let name = SieveNameStr::new(&"script").unwrap();
+        let commands = [
+            Pipelined::GetScript(name),
+            Pipelined::HaveSpace(name, 100),
+            Pipelined::SetActive(name),
+            Pipelined::DeleteScript(name),
+        ];
+        let (connection, responses) = block_on(connection.pipeline(&commands)).unwrap();
+
+        assert_eq!(
+            connection.stream.written,
+            b"GETSCRIPT \"script\"\r\nHAVESPACE \"script\" 100\r\nSETACTIVE \"script\"\r\n\
+              DELETESCRIPT \"script\"\r\n"
+        );
+        assert!(matches!(responses.as_slice(), [
+            PipelinedResponse::GetScript(Some(_)),
+            PipelinedResponse::HaveSpace(HaveSpace::InsufficientQuota { .. }),
+            PipelinedResponse::SetActive(SetActive::Ok),
+            PipelinedResponse::DeleteScript(DeleteScript::Nonexistent { .. }),
+        ]));
+    }
+}
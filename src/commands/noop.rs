@@ -0,0 +1,85 @@
+use futures::AsyncWriteExt;
+
+use crate::commands::handle_bye;
+use crate::parser::responses::response_oknobye;
+use crate::parser::{Response, Tag};
+use crate::state::{AuthMode, TlsMode};
+use crate::{commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, SieveError};
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode, MODE: AuthMode>
+    Connection<STREAM, TLS, MODE>
+{
+    /// Sends `NOOP`, optionally with a `tag` the server echoes in its response.
+    ///
+    /// If the response carries a different tag, it belongs to an earlier command and the client
+    /// cannot tell how many further responses are outstanding. The connection is closed and
+    /// [`SieveError::TagMismatch`] returned, the session cannot be resynchronized.
+    pub async fn noop(mut self, tag: Option<&str>) -> Result<Self, CommandError<Self>> {
+        let res = self.noop_inner(tag).await;
+        self.recover(res).map(|(connection, ())| connection)
+    }
+
+    pub(crate) async fn noop_inner(&mut self, tag: Option<&str>) -> Result<(), SieveError> {
+        self.send_command(commands::definitions::noop(tag)).await?;
+
+        let response = self.next_response(response_oknobye).await?;
+        let Response {
+            tag: response_tag,
+            info,
+        } = handle_bye(&mut self.stream, response).await?;
+
+        if let Tag::No(_) = response_tag {
+            return Err(SieveError::UnexpectedNo { info });
+        }
+
+        if let Some(tag) = tag {
+            let received = match info.code {
+                Some(ResponseCode::Tag(received)) => Some(received),
+                _ => None,
+            };
+            if received.as_deref() != Some(tag) {
+                // the response stream is out of sync with our commands
+                self.stream.close().await?;
+                return Err(SieveError::TagMismatch {
+                    expected: tag.into(),
+                    received,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    #[test]
+    fn test_tag() {
+        let stream = ScriptedStream::new(&[b"OK (TAG \"sync\") \"done\"\r\n"], 1024);
+
+        let connection = block_on(connection::<Authenticated>(stream).noop(Some("sync"))).unwrap();
+        assert_eq!(connection.stream.written, b"NOOP \"sync\"\r\n");
+    }
+
+    #[test]
+    fn test_tag_mismatch_closes_connection() {
+        let stream = ScriptedStream::new(&[b"OK (TAG \"stale\") \"done\"\r\n"], 1024);
+
+        let error = block_on(connection::<Authenticated>(stream).noop(Some("sync"))).unwrap_err();
+        assert!(
+            matches!(
+                &error.error,
+                SieveError::TagMismatch { expected, received: Some(received) }
+                    if expected == "sync" && received == "stale"
+            ),
+            "{error:?}"
+        );
+        assert!(error.connection.is_none());
+    }
+}
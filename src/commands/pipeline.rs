@@ -0,0 +1,141 @@
+use crate::commands::{definitions, DeleteScript, HaveSpace, SetActive};
+use crate::state::{Authenticated, TlsMode};
+use crate::{
+    AsyncRead, AsyncWrite, CommandError, Connection, Result, Script, SieveError, SieveNameStr,
+};
+
+// Only commands with small requests can be pipelined, otherwise the server could stop reading
+// while its responses are not read.
+#[derive(Debug, Clone, Copy)]
+pub enum Pipelined<'a> {
+    GetScript(&'a SieveNameStr),
+    HaveSpace(&'a SieveNameStr, u32),
+    SetActive(&'a SieveNameStr),
+    DeleteScript(&'a SieveNameStr),
+}
+
+#[derive(Debug)]
+pub enum PipelinedResponse {
+    GetScript(Option<Script>),
+    HaveSpace(HaveSpace),
+    SetActive(SetActive),
+    DeleteScript(DeleteScript),
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    pub async fn pipeline(
+        mut self,
+        commands: &[Pipelined<'_>],
+    ) -> Result<(Self, Vec<PipelinedResponse>), CommandError<Self>> {
+        let res = self.pipeline_inner(commands).await;
+        self.recover(res)
+    }
+
+    pub async fn get_scripts(
+        mut self,
+        names: &[&SieveNameStr],
+    ) -> Result<(Self, Vec<Option<Script>>), CommandError<Self>> {
+        let res = self.get_scripts_inner(names).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn pipeline_inner(
+        &mut self,
+        commands: &[Pipelined<'_>],
+    ) -> Result<Vec<PipelinedResponse>, SieveError> {
+        // a failing command must not leave the commands queued before it in the buffer
+        let queued = self.write_buf.len();
+        let res = async {
+            let mut command_names = Vec::with_capacity(commands.len());
+            for command in commands {
+                match *command {
+                    Pipelined::GetScript(name) => {
+                        self.queue_command(definitions::get_script(name)).await?
+                    }
+                    Pipelined::HaveSpace(name, size) => {
+                        self.queue_command(definitions::have_space(name, size)).await?
+                    }
+                    Pipelined::SetActive(name) => {
+                        self.queue_command(definitions::set_active(name)).await?
+                    }
+                    Pipelined::DeleteScript(name) => {
+                        self.queue_command(definitions::delete_script(name)).await?
+                    }
+                }
+                command_names.push(self.last_command);
+            }
+            Ok::<_, SieveError>(command_names)
+        }
+        .await;
+        if res.is_err() {
+            self.write_buf.truncate(queued);
+        }
+        let command_names = res?;
+        self.flush_commands().await?;
+
+        let mut responses = Vec::with_capacity(commands.len());
+        for (command, command_name) in commands.iter().zip(command_names) {
+            self.last_command = command_name;
+            let response = match command {
+                Pipelined::GetScript(_) => {
+                    PipelinedResponse::GetScript(self.get_script_response().await?)
+                }
+                Pipelined::HaveSpace(..) => {
+                    PipelinedResponse::HaveSpace(self.have_space_response().await?)
+                }
+                Pipelined::SetActive(_) => {
+                    PipelinedResponse::SetActive(self.set_active_response().await?)
+                }
+                Pipelined::DeleteScript(_) => {
+                    PipelinedResponse::DeleteScript(self.delete_script_response().await?)
+                }
+            };
+            responses.push(response);
+        }
+
+        Ok(responses)
+    }
+
+    pub(crate) async fn get_scripts_inner(
+        &mut self,
+        names: &[&SieveNameStr],
+    ) -> Result<Vec<Option<Script>>, SieveError> {
+        let commands: Vec<_> = names.iter().map(|name| Pipelined::GetScript(name)).collect();
+        let responses = self.pipeline_inner(&commands).await?;
+
+        Ok(responses
+            .into_iter()
+            .map(|response| match response {
+                PipelinedResponse::GetScript(script) => script,
+                _ => unreachable!("only `GETSCRIPT` commands were sent"),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_util::{connection, ScriptedStream};
+    use crate::SieveNameString;
+
+    // the second name is sent as a literal too large to be buffered, so the commands queued
+    // before it are written out and the write fails
+    #[test]
+    fn test_failure_midway_discards_queued_commands() {
+        let first = SieveNameString::new("first").unwrap();
+        let second = SieveNameString::new("\u{e9}".repeat(10 * 1024)).unwrap();
+        let mut connection =
+            connection::<Authenticated>(ScriptedStream::new(&[], 1024).with_failing_writes());
+
+        let res = block_on(
+            connection
+                .pipeline_inner(&[Pipelined::GetScript(&first), Pipelined::GetScript(&second)]),
+        );
+        assert!(matches!(res, Err(SieveError::Io(_))), "{res:?}");
+        assert!(connection.write_buf.is_empty());
+        assert!(connection.stream.closed);
+    }
+}
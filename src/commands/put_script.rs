@@ -1,12 +1,12 @@
 use tracing::warn;
 
-use crate::commands::{handle_bye, next_response};
+use crate::commands::handle_bye;
 use crate::parser::responses::response_oknobye;
 use crate::parser::{Response, Tag};
 use crate::state::{Authenticated, TlsMode};
 use crate::{
-    commands, AsyncRead, AsyncWrite, Connection, Quota, ResponseCode, ResponseInfo, SieveError,
-    SieveNameStr,
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, Quota, ResponseCode, ResponseInfo,
+    SieveError, SieveNameStr,
 };
 
 #[derive(Debug)]
@@ -27,11 +27,44 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
     pub async fn put_scripts(
         mut self,
         name: &SieveNameStr,
-        script: &str,
-    ) -> Result<(Self, PutScript), SieveError> {
+        script: impl AsRef<[u8]>,
+    ) -> Result<(Self, PutScript), CommandError<Self>> {
+        let res = self.put_script_inner(name, script.as_ref()).await;
+        self.recover(res)
+    }
+
+    pub async fn put_script_from(
+        mut self,
+        name: &SieveNameStr,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<(Self, PutScript), CommandError<Self>> {
+        let res = self.put_script_from_inner(name, len, script).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn put_script_inner(
+        &mut self,
+        name: &SieveNameStr,
+        script: &[u8],
+    ) -> Result<PutScript, SieveError> {
         self.send_command(commands::definitions::put_script(name, script)).await?;
+        self.put_script_response().await
+    }
+
+    pub(crate) async fn put_script_from_inner(
+        &mut self,
+        name: &SieveNameStr,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<PutScript, SieveError> {
+        self.send_command(commands::definitions::put_script_from(name, len, script))
+            .await?;
+        self.put_script_response().await
+    }
 
-        let response = next_response(&mut self.stream, response_oknobye).await?;
+    async fn put_script_response(&mut self) -> Result<PutScript, SieveError> {
+        let response = self.next_response(response_oknobye).await?;
         let Response {
             tag,
             info: ResponseInfo { code, human },
@@ -60,6 +93,6 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TL
             },
         };
 
-        Ok((self, res))
+        Ok(res)
     }
 }
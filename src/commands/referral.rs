@@ -0,0 +1,206 @@
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+
+use futures_rustls::rustls::ClientConfig;
+use tracing::debug;
+
+use crate::commands::{Authenticate, ConnectOptions};
+use crate::sasl::{Sasl, SaslError};
+use crate::state::{NoTls, TlsMode, Unauthenticated};
+use crate::{AsyncRead, AsyncWrite, Connection, SieveError, SieveUrl};
+
+pub trait Connector<STREAM> {
+    fn connect(&mut self, host: &str, port: u16) -> impl Future<Output = io::Result<STREAM>>;
+}
+
+impl<STREAM, F: FnMut(&str, u16) -> FUT, FUT: Future<Output = io::Result<STREAM>>> Connector<STREAM>
+    for F
+{
+    fn connect(&mut self, host: &str, port: u16) -> impl Future<Output = io::Result<STREAM>> {
+        self(host, port)
+    }
+}
+
+pub struct Referrals<C> {
+    pub connector: C,
+    pub max_hops: usize,
+    /// configuration for TLS connections to referred servers, the configuration of the referring
+    /// connection is used if this is `None`
+    pub tls_config: Option<Arc<ClientConfig>>,
+    /// whether referred servers use implicit TLS instead of `STARTTLS`
+    pub implicit_tls: bool,
+}
+
+impl<C> Referrals<C> {
+    pub fn new(connector: C) -> Self {
+        Referrals {
+            connector,
+            max_hops: 5,
+            tls_config: None,
+            implicit_tls: false,
+        }
+    }
+
+    // Connects to the server `url` refers to, following further referrals of that server.
+    // `options` are those of the referring connection.
+    async fn connect<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>(
+        &mut self,
+        url: &str,
+        hops: &mut usize,
+        options: ConnectOptions,
+    ) -> Result<Connection<STREAM, TLS, Unauthenticated>, SieveError>
+    where
+        C: Connector<STREAM>,
+    {
+        let options = ConnectOptions {
+            tls_config: self.tls_config.clone().or(options.tls_config),
+            ..options
+        };
+        let (mut stream, mut url) = self.follow(url, hops).await?;
+        loop {
+            let res = TLS::connect_referred(stream, &url, self.implicit_tls, options.clone()).await;
+            match res {
+                Err(err) => {
+                    let Some(next) = err.referral() else {
+                        return Err(err);
+                    };
+                    (stream, url) = self.follow(next, hops).await?;
+                }
+                Ok(connection) => return Ok(connection),
+            }
+        }
+    }
+
+    async fn follow<STREAM>(
+        &mut self,
+        url: &str,
+        hops: &mut usize,
+    ) -> Result<(STREAM, SieveUrl), SieveError>
+    where
+        C: Connector<STREAM>,
+    {
+        if *hops >= self.max_hops {
+            return Err(SieveError::TooManyReferrals { url: url.into() });
+        }
+        *hops += 1;
+
+        let url: SieveUrl =
+            url.parse().map_err(|_| SieveError::InvalidReferral { url: url.into() })?;
+        debug!("following referral to `{url}`");
+
+        let (host, port) = url.host_port();
+        let stream = self.connector.connect(host, port).await?;
+        Ok((stream, url))
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin> Connection<STREAM, NoTls, Unauthenticated> {
+    pub async fn connect_following_referrals<C: Connector<STREAM>>(
+        stream: STREAM,
+        options: ConnectOptions,
+        referrals: &mut Referrals<C>,
+    ) -> Result<Self, SieveError> {
+        match Self::connect_with_options(stream, options.clone()).await {
+            Err(err) => match err.referral() {
+                Some(url) => referrals.connect(url, &mut 0, options).await,
+                None => Err(err),
+            },
+            res => res,
+        }
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
+    Connection<STREAM, TLS, Unauthenticated>
+{
+    pub async fn authenticate_following_referrals<'a, E, S: Sasl<'a, Error = E>>(
+        self,
+        mut sasl: impl FnMut() -> S,
+        referrals: &mut Referrals<impl Connector<STREAM>>,
+    ) -> Result<Authenticate<E, STREAM, TLS>, SieveError> {
+        let mut hops = 0;
+        let options = ConnectOptions {
+            tls_config: self.tls_config.clone(),
+            timeouts: self.timeouts.clone(),
+        };
+        let mut connection = self;
+        loop {
+            let url = match connection.authenticate(sasl()).await {
+                Ok(Authenticate::Error {
+                    error: SaslError::Referral { url },
+                    ..
+                }) => url,
+                Err(err) => match err.referral() {
+                    Some(url) => url.to_owned(),
+                    None => return Err(err),
+                },
+                res => return res,
+            };
+
+            connection = referrals.connect(&url, &mut hops, options.clone()).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_util::ScriptedStream;
+    use crate::Timeouts;
+
+    const GREETING: &[u8] =
+        b"\"IMPLEMENTATION\" \"test\"\r\n\"SIEVE\" \"fileinto\"\r\n\"VERSION\" \"1.0\"\r\nOK\r\n";
+
+    fn referral(url: &str) -> ScriptedStream {
+        ScriptedStream::new(&[format!("BYE (REFERRAL \"{url}\") \"moved\"\r\n").as_bytes()], 1024)
+    }
+
+    #[test]
+    fn test_connect_following_referral() {
+        let mut hosts = Vec::new();
+        let mut referrals = Referrals::new(|host: &str, port| {
+            hosts.push((host.to_owned(), port));
+            std::future::ready(Ok(ScriptedStream::new(&[GREETING], 1024)))
+        });
+        let options = ConnectOptions {
+            tls_config: None,
+            timeouts: Some(Timeouts::new(|_| std::future::pending())),
+        };
+
+        let connection = block_on(Connection::connect_following_referrals(
+            referral("sieve://other.example.com:2000"),
+            options,
+            &mut referrals,
+        ))
+        .unwrap();
+        // the options of the first connection apply to the referred server as well
+        assert!(connection.timeouts().is_some());
+        drop(referrals);
+        assert_eq!(hosts, [("other.example.com".to_owned(), 2000)]);
+    }
+
+    #[test]
+    fn test_too_many_referrals() {
+        let mut hops = 0;
+        let mut referrals = Referrals::new(|_: &str, _| {
+            hops += 1;
+            std::future::ready(Ok(referral(&format!("sieve://hop{hops}.example.com"))))
+        });
+        referrals.max_hops = 2;
+
+        let res = block_on(Connection::connect_following_referrals(
+            referral("sieve://hop0.example.com"),
+            ConnectOptions::default(),
+            &mut referrals,
+        ));
+        assert!(
+            matches!(&res, Err(SieveError::TooManyReferrals { url }) if url == "sieve://hop2.example.com"),
+            "{res:?}"
+        );
+        drop(referrals);
+        assert_eq!(hops, 2);
+    }
+}
@@ -0,0 +1,232 @@
+use tracing::{debug, warn};
+
+use crate::commands::{handle_bye, DeleteScript, PutScript, SetActive};
+use crate::parser::responses::response_oknobye;
+use crate::parser::{Response, Tag};
+use crate::state::{Authenticated, TlsMode};
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, ResponseInfo, Result,
+    SieveError, SieveNameStr,
+};
+
+#[derive(Debug)]
+pub enum RenameScript {
+    Ok,
+    Nonexistent {
+        message: Option<String>,
+    },
+    AlreadyExists {
+        message: Option<String>,
+    },
+    Other {
+        message: Option<String>,
+    },
+    /// The emulation for servers predating RFC 5804 copied the script to the new name, but could
+    /// neither delete the old script nor the copy. Both scripts exist on the server.
+    Duplicated {
+        message: Option<String>,
+    },
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    pub async fn rename_script(
+        mut self,
+        old_name: &SieveNameStr,
+        new_name: &SieveNameStr,
+    ) -> Result<(Self, RenameScript), CommandError<Self>> {
+        let res = self.rename_script_inner(old_name, new_name).await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn rename_script_inner(
+        &mut self,
+        old_name: &SieveNameStr,
+        new_name: &SieveNameStr,
+    ) -> Result<RenameScript, SieveError> {
+        // servers predating RFC 5804 do not advertise `VERSION` and do not know `RENAMESCRIPT`
+        if !self.capabilities.supports_rfc5804() {
+            debug!("server does not support `RENAMESCRIPT`, emulating it");
+            return self.emulate_rename_script(old_name, new_name).await;
+        }
+
+        self.send_command(commands::definitions::rename_script(old_name, new_name))
+            .await?;
+
+        let response = self.next_response(response_oknobye).await?;
+        let Response {
+            tag,
+            info: ResponseInfo { code, human },
+        } = handle_bye(&mut self.stream, response).await?;
+
+        let res = match tag {
+            Tag::Ok(_) => RenameScript::Ok,
+            Tag::No(_) => match code {
+                Some(ResponseCode::Nonexistent) => RenameScript::Nonexistent { message: human },
+                Some(ResponseCode::AlreadyExists) => RenameScript::AlreadyExists { message: human },
+                code => {
+                    if let Some(code) = code {
+                        warn!("unexpected response code `{code}` in `NO` reply from `RENAMESCRIPT` command");
+                    }
+                    RenameScript::Other { message: human }
+                }
+            },
+        };
+
+        Ok(res)
+    }
+
+    // see section 2.11.1 of rfc 5804
+    async fn emulate_rename_script(
+        &mut self,
+        old_name: &SieveNameStr,
+        new_name: &SieveNameStr,
+    ) -> Result<RenameScript, SieveError> {
+        let scripts = self.list_scripts_inner().await?;
+
+        let Some(active) = scripts
+            .iter()
+            .find(|(name, _)| name.as_sieve_name_str() == old_name)
+            .map(|(_, active)| *active)
+        else {
+            return Ok(RenameScript::Nonexistent { message: None });
+        };
+        if scripts.iter().any(|(name, _)| name.as_sieve_name_str() == new_name) {
+            return Ok(RenameScript::AlreadyExists { message: None });
+        }
+
+        let Some(script) = self.get_script_inner(old_name).await? else {
+            return Ok(RenameScript::Nonexistent { message: None });
+        };
+
+        match self.put_script_inner(new_name, script.as_bytes()).await? {
+            PutScript::Ok { .. } => {}
+            PutScript::InvalidScript { error: message }
+            | PutScript::InsufficientQuota { message, .. } => {
+                return Ok(RenameScript::Other { message });
+            }
+        }
+
+        if active {
+            match self.set_active_inner(new_name).await? {
+                SetActive::Ok => {}
+                SetActive::Nonexistent { message } | SetActive::Other { message } => {
+                    return self.undo_copy(old_name, new_name, false, message).await;
+                }
+            }
+        }
+
+        match self.delete_script_inner(old_name).await? {
+            DeleteScript::Ok => Ok(RenameScript::Ok),
+            // the old script vanished meanwhile, so only the renamed one is left
+            DeleteScript::Nonexistent { .. } => Ok(RenameScript::Ok),
+            DeleteScript::Active { message } | DeleteScript::Other { message } => {
+                self.undo_copy(old_name, new_name, active, message).await
+            }
+        }
+    }
+
+    // Deletes the copy of an emulated rename which failed, activating the old script again if the
+    // copy was activated. `message` describes the original failure.
+    async fn undo_copy(
+        &mut self,
+        old_name: &SieveNameStr,
+        new_name: &SieveNameStr,
+        reactivate: bool,
+        message: Option<String>,
+    ) -> Result<RenameScript, SieveError> {
+        if reactivate && !matches!(self.set_active_inner(old_name).await?, SetActive::Ok) {
+            return Ok(RenameScript::Duplicated { message });
+        }
+
+        let res = match self.delete_script_inner(new_name).await? {
+            DeleteScript::Ok => RenameScript::Other { message },
+            _ => RenameScript::Duplicated { message },
+        };
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+    use crate::Version;
+
+    const COMMANDS: &str = "LISTSCRIPTS\r\nGETSCRIPT \"old\"\r\nPUTSCRIPT \"new\" \"keep;\"\r\n";
+
+    // renames the active script `old` on a server without `RENAMESCRIPT`, returning the result and
+    // the commands sent after `PUTSCRIPT`
+    fn emulate(responses: &[&[u8]]) -> (RenameScript, String) {
+        let listed: &[&[u8]] = &[
+            b"\"old\" ACTIVE\r\n\"other\"\r\nOK\r\n",
+            b"{5}\r\nkeep;\r\nOK\r\n",
+            b"OK\r\n",
+        ];
+        let stream = ScriptedStream::new(&[listed, responses].concat(), usize::MAX);
+        let mut connection = connection::<Authenticated>(stream);
+        connection.capabilities.version = Version { major: 0, minor: 0 };
+
+        let old_name = SieveNameStr::new(&"old").unwrap();
+        let new_name = SieveNameStr::new(&"new").unwrap();
+        let (connection, res) = block_on(connection.rename_script(old_name, new_name)).unwrap();
+
+        let written = String::from_utf8(connection.stream.written).unwrap();
+        let rest = written.strip_prefix(COMMANDS).unwrap_or_else(|| panic!("{written}"));
+        (res, rest.to_owned())
+    }
+
+    #[test]
+    fn test_emulated_rename() {
+        let (res, written) = emulate(&[b"OK\r\n", b"OK\r\n"]);
+        assert!(matches!(res, RenameScript::Ok));
+        assert_eq!(written, "SETACTIVE \"new\"\r\nDELETESCRIPT \"old\"\r\n");
+    }
+
+    #[test]
+    fn test_emulated_rename_old_script_vanished() {
+        let (res, written) = emulate(&[b"OK\r\n", b"NO (NONEXISTENT)\r\n"]);
+        assert!(matches!(res, RenameScript::Ok));
+        assert_eq!(written, "SETACTIVE \"new\"\r\nDELETESCRIPT \"old\"\r\n");
+    }
+
+    #[test]
+    fn test_emulated_rename_set_active_fails() {
+        let (res, written) = emulate(&[b"NO \"failed\"\r\n", b"OK\r\n"]);
+        assert!(matches!(res, RenameScript::Other { message: Some(m) } if m == "failed"));
+        assert_eq!(written, "SETACTIVE \"new\"\r\nDELETESCRIPT \"new\"\r\n");
+    }
+
+    #[test]
+    fn test_emulated_rename_delete_fails() {
+        let (res, written) = emulate(&[b"OK\r\n", b"NO \"failed\"\r\n", b"OK\r\n", b"OK\r\n"]);
+        assert!(matches!(res, RenameScript::Other { message: Some(m) } if m == "failed"));
+        assert_eq!(
+            written,
+            "SETACTIVE \"new\"\r\nDELETESCRIPT \"old\"\r\nSETACTIVE \"old\"\r\n\
+             DELETESCRIPT \"new\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_emulated_rename_rollback_fails() {
+        let (res, written) = emulate(&[
+            b"OK\r\n",
+            b"NO \"failed\"\r\n",
+            b"OK\r\n",
+            b"NO \"still failing\"\r\n",
+        ]);
+        assert!(matches!(res, RenameScript::Duplicated { message: Some(m) } if m == "failed"));
+        assert_eq!(
+            written,
+            "SETACTIVE \"new\"\r\nDELETESCRIPT \"old\"\r\nSETACTIVE \"old\"\r\n\
+             DELETESCRIPT \"new\"\r\n"
+        );
+
+        let (res, written) = emulate(&[b"OK\r\n", b"NO \"failed\"\r\n", b"NO\r\n"]);
+        assert!(matches!(res, RenameScript::Duplicated { .. }));
+        assert_eq!(written, "SETACTIVE \"new\"\r\nDELETESCRIPT \"old\"\r\nSETACTIVE \"old\"\r\n");
+    }
+}
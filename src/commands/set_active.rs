@@ -0,0 +1,109 @@
+use tracing::warn;
+
+use crate::commands::handle_bye;
+use crate::parser::responses::response_oknobye;
+use crate::parser::{Response, Tag};
+use crate::state::{Authenticated, TlsMode};
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ResponseCode, ResponseInfo, Result,
+    SieveError, SieveNameStr,
+};
+
+#[derive(Debug)]
+pub enum SetActive {
+    Ok,
+    Nonexistent { message: Option<String> },
+    Other { message: Option<String> },
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    pub async fn set_active(
+        mut self,
+        name: &SieveNameStr,
+    ) -> Result<(Self, SetActive), CommandError<Self>> {
+        let res = self.set_active_inner(name).await;
+        self.recover(res)
+    }
+
+    pub async fn deactivate_all(mut self) -> Result<(Self, SetActive), CommandError<Self>> {
+        let res = self.deactivate_all_inner().await;
+        self.recover(res)
+    }
+
+    pub(crate) async fn set_active_inner(
+        &mut self,
+        name: &SieveNameStr,
+    ) -> Result<SetActive, SieveError> {
+        self.send_command(commands::definitions::set_active(name)).await?;
+        self.set_active_response().await
+    }
+
+    pub(crate) async fn set_active_response(&mut self) -> Result<SetActive, SieveError> {
+        let response = self.next_response(response_oknobye).await?;
+        let Response {
+            tag,
+            info: ResponseInfo { code, human },
+        } = handle_bye(&mut self.stream, response).await?;
+
+        let res = match tag {
+            Tag::Ok(_) => SetActive::Ok,
+            Tag::No(_) => match code {
+                Some(ResponseCode::Nonexistent) => SetActive::Nonexistent { message: human },
+                code => {
+                    if let Some(code) = code {
+                        warn!("unexpected response code `{code}` in `NO` reply from `SETACTIVE` command");
+                    }
+                    SetActive::Other { message: human }
+                }
+            },
+        };
+
+        Ok(res)
+    }
+
+    pub(crate) async fn deactivate_all_inner(&mut self) -> Result<SetActive, SieveError> {
+        let empty = SieveNameStr::new(&"").expect("empty script name is valid");
+        self.set_active_inner(empty).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::test_util::{connection, ScriptedStream};
+
+    #[test]
+    fn test_activate() {
+        let name = SieveNameStr::new(&"vacation").unwrap();
+        let stream = ScriptedStream::new(&[b"OK\r\n"], 1024);
+
+        let (connection, res) =
+            block_on(connection::<Authenticated>(stream).set_active(name)).unwrap();
+        assert!(matches!(res, SetActive::Ok), "{res:?}");
+        assert_eq!(connection.stream.written, b"SETACTIVE \"vacation\"\r\n");
+    }
+
+    #[test]
+    fn test_deactivate_all() {
+        let stream = ScriptedStream::new(&[b"OK\r\n"], 1024);
+
+        let (connection, res) =
+            block_on(connection::<Authenticated>(stream).deactivate_all()).unwrap();
+        assert!(matches!(res, SetActive::Ok), "{res:?}");
+        assert_eq!(connection.stream.written, b"SETACTIVE \"\"\r\n");
+    }
+
+    #[test]
+    fn test_nonexistent() {
+        let name = SieveNameStr::new(&"missing").unwrap();
+        let stream = ScriptedStream::new(&[b"NO (NONEXISTENT) \"no such script\"\r\n"], 1024);
+
+        let (_, res) = block_on(connection::<Authenticated>(stream).set_active(name)).unwrap();
+        assert!(
+            matches!(&res, SetActive::Nonexistent { message } if message.as_deref() == Some("no such script")),
+            "{res:?}"
+        );
+    }
+}
@@ -1,52 +1,157 @@
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::AsyncWriteExt;
+use futures_rustls::client::TlsStream;
 use futures_rustls::pki_types::ServerName;
 use futures_rustls::rustls::ClientConfig;
 use futures_rustls::TlsConnector;
 use rustls_platform_verifier::ConfigVerifierExt;
 use tracing::warn;
 
-use crate::capabilities::verify_capabilities;
-use crate::commands::{handle_bye, next_response};
-use crate::parser::responses::{response_capability, response_oknobye};
+use crate::commands::{handle_bye, ConnectOptions};
+use crate::parser::responses::response_oknobye;
 use crate::parser::Response;
+use crate::pinning::CertificateMismatch;
 use crate::state::{NoTls, Tls, Unauthenticated};
-use crate::{commands, AsyncRead, AsyncWrite, Connection, SieveError};
+use crate::timeout::with_timeout;
+use crate::{
+    commands, AsyncRead, AsyncWrite, CommandError, Connection, ParseError, SieveError, Timeouts,
+};
 
+// The TLS handshake is subject to the timeouts the connection was created with.
 impl<STREAM: AsyncRead + AsyncWrite + Unpin> Connection<STREAM, NoTls, Unauthenticated> {
+    /// Upgrades the connection to TLS, using the TLS configuration of the [`ConnectOptions`] the
+    /// connection was created with.
+    ///
+    /// [`ConnectOptions`]: crate::commands::ConnectOptions
     pub async fn start_tls(
+        self,
+        server_name: ServerName<'static>,
+    ) -> Result<Connection<STREAM, Tls, Unauthenticated>, CommandError<Self>> {
+        let config = match &self.tls_config {
+            Some(config) => config.clone(),
+            None => match platform_config() {
+                Ok(config) => config,
+                Err(error) => {
+                    return Err(CommandError {
+                        connection: Some(self),
+                        error,
+                    })
+                }
+            },
+        };
+        self.start_tls_with_config(server_name, config).await
+    }
+
+    /// Upgrades the connection to TLS using `config`, regardless of the configuration the
+    /// connection was created with.
+    pub async fn start_tls_with_config(
         mut self,
         server_name: ServerName<'static>,
-    ) -> Result<Connection<STREAM, Tls, Unauthenticated>, SieveError> {
+        config: Arc<ClientConfig>,
+    ) -> Result<Connection<STREAM, Tls, Unauthenticated>, CommandError<Self>> {
+        let res = self.start_tls_inner().await;
+        let (connection, ()) = self.recover(res)?;
+        Ok(connection.tls_handshake(server_name, config).await?)
+    }
+
+    async fn start_tls_inner(&mut self) -> Result<(), SieveError> {
         if !self.capabilities.start_tls {
             warn!("server does not support TLS");
         }
 
         self.send_command(commands::definitions::start_tls).await?;
 
-        let response = next_response(&mut self.stream, response_oknobye).await?;
+        let response = self.next_response(response_oknobye).await?;
         let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
         if tag.is_no() {
             return Err(SieveError::UnexpectedNo { info });
         }
 
-        let config = ClientConfig::with_platform_verifier().map_err(io::Error::other)?;
-        let config = TlsConnector::from(Arc::new(config));
-
-        let mut stream =
-            config.connect(server_name, self.stream).await.map_err(SieveError::from)?;
+        Ok(())
+    }
 
-        let (capabilities, response) = next_response(&mut stream, response_capability).await?;
-        let Response { tag, info } = handle_bye(&mut stream, response).await?;
-        if tag.is_no() {
-            return Err(SieveError::UnexpectedNo { info });
+    async fn tls_handshake(
+        self,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> Result<Connection<STREAM, Tls, Unauthenticated>, SieveError> {
+        // data sent before the handshake must not be mistaken for a response protected by TLS
+        if !self.read_buf.is_empty() {
+            let mut stream = self.stream;
+            stream.close().await?;
+            return Err(SieveError::Syntax(ParseError::new(
+                self.last_command,
+                "end of response",
+                &self.read_buf,
+                0,
+            )));
         }
 
-        Ok(Connection {
-            stream,
-            capabilities: verify_capabilities(capabilities)?,
-            _p: Default::default(),
+        let stream =
+            tls_connect(self.stream, server_name, config, self.timeouts.as_ref(), |timeouts| {
+                timeouts.command
+            })
+            .await?;
+        let options = ConnectOptions {
+            tls_config: self.tls_config,
+            timeouts: self.timeouts,
+        };
+        Connection::read_capabilities(stream, self.last_command, options, |timeouts| {
+            timeouts.command
         })
+        .await
+    }
+}
+
+pub(crate) fn platform_config() -> Result<Arc<ClientConfig>, SieveError> {
+    let config = ClientConfig::with_platform_verifier().map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
+
+pub(crate) async fn tls_connect<STREAM: AsyncRead + AsyncWrite + Unpin>(
+    stream: STREAM,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    timeouts: Option<&Timeouts>,
+    timeout: fn(&Timeouts) -> Option<Duration>,
+) -> Result<TlsStream<STREAM>, SieveError> {
+    let config = TlsConnector::from(config);
+
+    with_timeout(timeouts, timeout, async {
+        config.connect(server_name, stream).await.map_err(|error| {
+            match CertificateMismatch::from_io_error(&error) {
+                Some(mismatch) => SieveError::CertificateMismatch(mismatch.clone()),
+                None => SieveError::Io(error),
+            }
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::pinning::PinnedVerifier;
+    use crate::test_util::{connection, ScriptedStream};
+
+    #[test]
+    fn test_start_tls_with_config_rejected() {
+        let stream = ScriptedStream::new(&[b"NO \"not now\"\r\n"], 1024);
+        let config = PinnedVerifier::new([]).into_config();
+        let error = block_on(
+            connection::<Unauthenticated>(stream)
+                .start_tls_with_config(ServerName::try_from("example.com").unwrap(), config),
+        )
+        .unwrap_err();
+
+        assert!(matches!(error.error, SieveError::UnexpectedNo { .. }), "{error:?}");
+        let connection = error.connection.unwrap();
+        assert_eq!(connection.stream.written, b"STARTTLS\r\n");
+        assert!(!connection.stream.closed);
     }
 }
@@ -0,0 +1,144 @@
+use tracing::warn;
+
+use crate::commands::handle_bye;
+use crate::parser::responses::response_oknobye;
+use crate::parser::{Response, Tag};
+use crate::state::{Authenticated, TlsMode, Unauthenticated};
+use crate::{commands, AsyncRead, AsyncWrite, CommandError, Connection};
+
+#[derive(Debug)]
+pub enum Unauthenticate<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> {
+    Ok {
+        connection: Connection<STREAM, TLS, Unauthenticated>,
+    },
+    Unsupported {
+        connection: Connection<STREAM, TLS, Authenticated>,
+    },
+    Error {
+        connection: Connection<STREAM, TLS, Authenticated>,
+        message: Option<String>,
+    },
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    // Errors after the server accepted the command hand back the connection in the unauthenticated
+    // state, with the capabilities from before.
+    pub async fn unauthenticate(
+        mut self,
+    ) -> Result<Unauthenticate<STREAM, TLS>, CommandError<Connection<STREAM, TLS, Unauthenticated>>>
+    {
+        let supported = self
+            .capabilities
+            .others
+            .keys()
+            .any(|capability| capability.eq_ignore_ascii_case("UNAUTHENTICATE"));
+        if !supported {
+            return Ok(Unauthenticate::Unsupported { connection: self });
+        }
+
+        self.send_command(commands::definitions::unauthenticate).await?;
+
+        let response = self.next_response(response_oknobye).await?;
+        let Response { tag, info } = handle_bye(&mut self.stream, response).await?;
+
+        if let Tag::No(_) = tag {
+            if let Some(code) = info.code {
+                warn!(
+                    "unexpected response code `{code}` in `NO` reply from `UNAUTHENTICATE` command"
+                );
+            }
+            return Ok(Unauthenticate::Error {
+                connection: self,
+                message: info.human,
+            });
+        }
+
+        let mut connection = Connection {
+            stream: self.stream,
+            capabilities: self.capabilities,
+            last_command: self.last_command,
+            poisoned: false,
+            read_buf: self.read_buf,
+            write_buf: self.write_buf,
+            timeouts: self.timeouts,
+            tls_config: self.tls_config,
+            in_flight: false,
+            _p: Default::default(),
+        };
+        // RFC 5804, section 2.14 does not have the server send its capabilities after `OK`. Some
+        // servers do so anyway, right along with the `OK`, and the response to another
+        // `CAPABILITY` would be left unread.
+        let res = if connection.read_buf.is_empty() {
+            connection.refresh_capabilities_inner().await
+        } else {
+            connection.capabilities_response().await.map(|capabilities| {
+                connection.capabilities = capabilities;
+            })
+        };
+        let (connection, ()) = connection.recover(res)?;
+
+        Ok(Unauthenticate::Ok { connection })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::state::NoTls;
+    use crate::test_util::{connection, ScriptedStream};
+
+    const CAPABILITIES: &[u8] =
+        b"\"IMPLEMENTATION\" \"after\"\r\n\"SIEVE\" \"fileinto\"\r\n\"VERSION\" \"1.0\"\r\nOK\r\n";
+
+    fn authenticated(reads: &[&[u8]]) -> Connection<ScriptedStream, NoTls, Authenticated> {
+        let mut connection = connection::<Authenticated>(ScriptedStream::new(reads, 1024));
+        connection.capabilities.others = HashMap::from([("UNAUTHENTICATE".to_owned(), None)]);
+        connection
+    }
+
+    #[test]
+    fn test_unauthenticate() {
+        let connection = authenticated(&[b"OK\r\n", CAPABILITIES]);
+
+        let res = block_on(connection.unauthenticate()).unwrap();
+        let Unauthenticate::Ok { connection } = res else {
+            panic!("`OK` must return to the unauthenticated state");
+        };
+        assert_eq!(connection.capabilities().implementation, "after");
+        assert_eq!(connection.stream.written, b"UNAUTHENTICATE\r\nCAPABILITY\r\n");
+    }
+
+    #[test]
+    fn test_unsolicited_capabilities() {
+        let response = [b"OK\r\n".as_slice(), CAPABILITIES].concat();
+        let connection = authenticated(&[&response]);
+
+        let res = block_on(connection.unauthenticate()).unwrap();
+        let Unauthenticate::Ok { connection } = res else {
+            panic!("`OK` must return to the unauthenticated state");
+        };
+        assert_eq!(connection.capabilities().implementation, "after");
+        assert_eq!(connection.stream.written, b"UNAUTHENTICATE\r\n");
+        assert!(connection.read_buf.is_empty());
+    }
+
+    #[test]
+    fn test_no() {
+        let connection = authenticated(&[b"NO \"not now\"\r\n"]);
+
+        let res = block_on(connection.unauthenticate()).unwrap();
+        let Unauthenticate::Error {
+            connection,
+            message,
+        } = res
+        else {
+            panic!("`NO` must keep the authenticated state");
+        };
+        assert_eq!(message.as_deref(), Some("not now"));
+        assert_eq!(connection.capabilities().implementation, "test");
+    }
+}
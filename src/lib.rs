@@ -4,17 +4,34 @@ use std::any::type_name;
 use std::fmt::{Debug, Display, Formatter};
 use std::io;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 mod capabilities;
+mod channel_binding;
 pub mod commands;
 mod parser;
+pub mod pinning;
 pub mod sasl;
+mod script;
+mod session;
 mod sieve_name;
+mod sieve_url;
+#[cfg(test)]
+mod test_util;
+mod timeout;
 
 pub use capabilities::{Capabilities, CapabilitiesError, Version};
+pub use channel_binding::{ChannelBindingError, ChannelBindingType};
 pub use futures::{AsyncRead, AsyncWrite};
 pub use futures_rustls::pki_types::ServerName;
+pub use futures_rustls::rustls;
+use futures_rustls::rustls::ClientConfig;
+use pinning::CertificateMismatch;
+pub use script::Script;
+pub use session::Session;
 pub use sieve_name::{SieveNameError, SieveNameStr, SieveNameString};
+pub use sieve_url::{SieveUrl, SieveUrlError};
+pub use timeout::{Timeouts, Timer};
 
 pub mod state {
     use futures_rustls::client::TlsStream;
@@ -48,9 +65,54 @@ pub mod state {
     }
 
     mod private_tls_mode {
-        pub trait Sealed {}
-        impl Sealed for super::Tls {}
-        impl Sealed for super::NoTls {}
+        use std::future::Future;
+
+        use super::{NoTls, Tls, TlsMode, Unauthenticated};
+        use crate::commands::ConnectOptions;
+        use crate::{AsyncRead, AsyncWrite, Connection, SieveError, SieveUrl};
+
+        pub trait Sealed: Sized {
+            /// Connects to a referred server in this TLS mode.
+            fn connect_referred<STREAM: AsyncRead + AsyncWrite + Unpin>(
+                stream: STREAM,
+                url: &SieveUrl,
+                implicit_tls: bool,
+                options: ConnectOptions,
+            ) -> impl Future<Output = Result<Connection<STREAM, Self, Unauthenticated>, SieveError>>
+            where
+                Self: TlsMode;
+        }
+
+        impl Sealed for NoTls {
+            async fn connect_referred<STREAM: AsyncRead + AsyncWrite + Unpin>(
+                stream: STREAM,
+                _url: &SieveUrl,
+                _implicit_tls: bool,
+                options: ConnectOptions,
+            ) -> Result<Connection<STREAM, Self, Unauthenticated>, SieveError> {
+                Connection::connect_with_options(stream, options).await
+            }
+        }
+
+        impl Sealed for Tls {
+            async fn connect_referred<STREAM: AsyncRead + AsyncWrite + Unpin>(
+                stream: STREAM,
+                url: &SieveUrl,
+                implicit_tls: bool,
+                options: ConnectOptions,
+            ) -> Result<Connection<STREAM, Self, Unauthenticated>, SieveError> {
+                let server_name = url.server_name().map_err(|_| SieveError::InvalidReferral {
+                    url: url.to_string(),
+                })?;
+
+                if implicit_tls {
+                    Connection::connect_tls_with_options(stream, server_name, options).await
+                } else {
+                    let connection = Connection::connect_with_options(stream, options).await?;
+                    Ok(connection.start_tls(server_name).await?)
+                }
+            }
+        }
     }
 }
 
@@ -61,6 +123,17 @@ pub struct Connection<
 > {
     pub(crate) stream: TLS::Stream<STREAM>,
     pub(crate) capabilities: Capabilities,
+    pub(crate) last_command: Option<&'static str>,
+    pub(crate) poisoned: bool,
+    // data received after the last response
+    pub(crate) read_buf: Vec<u8>,
+    // commands not yet written to `stream`
+    pub(crate) write_buf: Vec<u8>,
+    pub(crate) timeouts: Option<Timeouts>,
+    // used for the handshake after `STARTTLS`
+    pub(crate) tls_config: Option<Arc<ClientConfig>>,
+    // set while a command of a `Session` runs, so an interrupted command can be detected
+    pub(crate) in_flight: bool,
     pub(crate) _p: PhantomData<MODE>,
 }
 
@@ -81,6 +154,14 @@ impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: state::TlsMode, AUTH: state::A
     pub fn capabilities(&self) -> &Capabilities {
         &self.capabilities
     }
+
+    pub fn timeouts(&self) -> Option<&Timeouts> {
+        self.timeouts.as_ref()
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Option<Timeouts>) {
+        self.timeouts = timeouts;
+    }
 }
 
 type Result<T, E = SieveError> = core::result::Result<T, E>;
@@ -90,8 +171,8 @@ pub enum SieveError {
     #[error("encountered I/0 error")]
     Io(#[from] io::Error),
 
-    #[error("syntax error")]
-    Syntax,
+    #[error("syntax error: {0}")]
+    Syntax(ParseError),
 
     #[error(transparent)]
     CapabilitiesError(#[from] CapabilitiesError),
@@ -101,6 +182,157 @@ pub enum SieveError {
 
     #[error("received an unexpected `NO` response: {info}")]
     UnexpectedNo { info: ResponseInfo },
+
+    #[error(fmt = fmt_tag_mismatch)]
+    TagMismatch {
+        expected: String,
+        received: Option<String>,
+    },
+
+    #[error("received invalid referral `{url}`")]
+    InvalidReferral { url: String },
+
+    #[error("exceeded the maximum number of referrals, last referral was `{url}`")]
+    TooManyReferrals { url: String },
+
+    #[error("connection is unusable after a previous error")]
+    Poisoned,
+
+    #[error("timed out waiting for the server")]
+    Timeout,
+
+    #[error("a previous command was interrupted, the connection is out of sync with the server")]
+    Desynchronized,
+
+    #[error(transparent)]
+    CertificateMismatch(#[from] CertificateMismatch),
+
+    #[error("failed to write the script to the sink")]
+    Sink(#[source] io::Error),
+
+    #[error("failed to read the script from the source")]
+    Source(#[source] io::Error),
+
+    #[error("string of {len} bytes is too long to be sent as a literal")]
+    LiteralTooLong { len: usize },
+}
+
+impl SieveError {
+    // the connection is still usable after these errors
+    pub(crate) fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            SieveError::UnexpectedNo { .. }
+                | SieveError::CapabilitiesError(_)
+                | SieveError::Sink(_)
+                | SieveError::Source(_)
+                | SieveError::LiteralTooLong { .. }
+        )
+    }
+
+    pub fn referral(&self) -> Option<&str> {
+        match self {
+            SieveError::Bye {
+                info:
+                    ResponseInfo {
+                        code: Some(ResponseCode::Referral(url)),
+                        ..
+                    },
+            } => Some(url),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by commands which hands the connection back if it is still usable.
+#[derive(Debug)]
+pub struct CommandError<C> {
+    /// `None` if the error left the connection unusable
+    pub connection: Option<C>,
+    pub error: SieveError,
+}
+
+impl<C> Display for CommandError<C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl<C: Debug> std::error::Error for CommandError<C> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl<C> From<SieveError> for CommandError<C> {
+    fn from(error: SieveError) -> Self {
+        CommandError {
+            connection: None,
+            error,
+        }
+    }
+}
+
+impl<C> From<CommandError<C>> for SieveError {
+    fn from(error: CommandError<C>) -> Self {
+        error.error
+    }
+}
+
+fn fmt_tag_mismatch(
+    expected: &String,
+    received: &Option<String>,
+    formatter: &mut Formatter,
+) -> std::fmt::Result {
+    write!(formatter, "expected response tagged `{expected}`, but ")?;
+    match received {
+        Some(received) => write!(formatter, "received tag `{received}`"),
+        None => write!(formatter, "received untagged response"),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub command: Option<&'static str>,
+    pub expected: &'static str,
+    pub offset: usize,
+    pub input: Vec<u8>,
+    pub truncated: bool,
+}
+
+impl ParseError {
+    const MAX_INPUT_LEN: usize = 128;
+
+    pub(crate) fn new(
+        command: Option<&'static str>,
+        expected: &'static str,
+        buf: &[u8],
+        offset: usize,
+    ) -> Self {
+        let input = &buf[offset.min(buf.len())..];
+        let truncated = input.len() > Self::MAX_INPUT_LEN;
+        ParseError {
+            command,
+            expected,
+            offset,
+            input: input[..input.len().min(Self::MAX_INPUT_LEN)].to_vec(),
+            truncated,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to parse {}", self.expected)?;
+        if let Some(command) = self.command {
+            write!(f, " to `{command}` command")?;
+        }
+        write!(f, " at offset {}: \"{}\"", self.offset, self.input.escape_ascii())?;
+        if self.truncated {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -212,3 +444,75 @@ impl Display for ResponseInfo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::state::Authenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    fn parse_error(response: &[u8]) -> ParseError {
+        let connection = connection::<Authenticated>(ScriptedStream::new(&[response], 1024));
+        let error = block_on(connection.noop(None)).unwrap_err();
+        assert!(error.connection.is_none());
+        match error.error {
+            SieveError::Syntax(error) => error,
+            error => panic!("unexpected error {error:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let response = b"\"a\" ACTIVE\r\n\"b\" BOGUS\r\nOK\r\n";
+        let connection = connection::<Authenticated>(ScriptedStream::new(&[response], 1024));
+        let error = block_on(connection.list_scripts()).unwrap_err();
+        assert!(error.connection.is_none());
+        let SieveError::Syntax(error) = error.error else {
+            panic!("unexpected error {error:?}");
+        };
+
+        assert_eq!(error.command, Some("LISTSCRIPTS"));
+        assert_eq!(error.expected, "script listing");
+        assert_eq!(error.offset, 12);
+        assert_eq!(error.input, b"\"b\" BOGUS\r\nOK\r\n");
+        assert!(!error.truncated);
+        assert_eq!(
+            error.to_string(),
+            "failed to parse script listing to `LISTSCRIPTS` command at offset 12: \
+             \"\\\"b\\\" BOGUS\\r\\nOK\\r\\n\""
+        );
+    }
+
+    #[test]
+    fn test_parse_error_truncated() {
+        let mut response = vec![b'X'; 200];
+        response.extend_from_slice(b"\r\n");
+        let error = parse_error(&response);
+
+        assert_eq!(error.command, Some("NOOP"));
+        assert_eq!(error.expected, "OK, NO or BYE response");
+        assert_eq!(error.offset, 0);
+        assert_eq!(error.input, [b'X'; ParseError::MAX_INPUT_LEN]);
+        assert!(error.truncated);
+        assert!(error.to_string().ends_with("XX\"..."));
+    }
+
+    #[test]
+    fn test_parse_error_not_truncated_at_limit() {
+        let buf = [b'X'; ParseError::MAX_INPUT_LEN + 10];
+
+        let error = ParseError::new(None, "response", &buf, 10);
+        assert_eq!(error.input.len(), ParseError::MAX_INPUT_LEN);
+        assert!(!error.truncated);
+
+        let error = ParseError::new(None, "response", &buf, buf.len() + 1);
+        assert!(error.input.is_empty());
+        assert!(!error.truncated);
+        assert_eq!(
+            error.to_string(),
+            format!("failed to parse response at offset {}: \"\"", buf.len() + 1)
+        );
+    }
+}
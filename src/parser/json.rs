@@ -0,0 +1,180 @@
+use winnow::ascii::multispace0;
+use winnow::combinator::{alt, delimited, preceded, repeat, separated, separated_pair};
+use winnow::token::{take_till, take_while};
+use winnow::{ModalResult as PResult, Parser};
+
+// Flat JSON object as sent in SASL error challenges (RFC 7628, section 3.2.2). Values which are not
+// strings are returned as written.
+pub fn json_object(input: &mut &str) -> PResult<Vec<(String, String)>> {
+    delimited(
+        ("{", multispace0),
+        separated(
+            0..,
+            separated_pair(json_string, (multispace0, ":", multispace0), json_value),
+            (multispace0, ",", multispace0),
+        ),
+        (multispace0, "}", multispace0),
+    )
+    .parse_next(input)
+}
+
+fn json_value(input: &mut &str) -> PResult<String> {
+    alt((
+        json_string,
+        take_while(1.., |c: char| !matches!(c, ',' | '}' | '{' | '[') && !c.is_whitespace())
+            .map(ToOwned::to_owned),
+    ))
+    .parse_next(input)
+}
+
+fn json_string(input: &mut &str) -> PResult<String> {
+    delimited(
+        "\"",
+        repeat(
+            0..,
+            alt((
+                take_till(1.., ['"', '\\']).map(ToOwned::to_owned),
+                preceded("\\", json_escape).map(String::from),
+            )),
+        )
+        .fold(String::new, |mut string, part: String| {
+            string.push_str(&part);
+            string
+        }),
+        "\"",
+    )
+    .parse_next(input)
+}
+
+fn json_escape(input: &mut &str) -> PResult<char> {
+    alt((
+        '"'.value('"'),
+        '\\'.value('\\'),
+        '/'.value('/'),
+        'b'.value('\u{8}'),
+        'f'.value('\u{c}'),
+        'n'.value('\n'),
+        'r'.value('\r'),
+        't'.value('\t'),
+        preceded('u', json_unicode_escape),
+    ))
+    .parse_next(input)
+}
+
+fn json_unicode_escape(input: &mut &str) -> PResult<char> {
+    let hex4 =
+        || take_while(4, |c: char| c.is_ascii_hexdigit()).try_map(|s| u16::from_str_radix(s, 16));
+    alt((
+        // surrogate pair
+        separated_pair(
+            hex4().verify(|high| (0xd800..0xdc00).contains(high)),
+            "\\u",
+            hex4().verify(|low| (0xdc00..0xe000).contains(low)),
+        )
+        .verify_map(|(high, low)| char::decode_utf16([high, low]).next()?.ok()),
+        hex4().verify_map(|c| char::from_u32(u32::from(c))),
+    ))
+    .parse_next(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! assert_parse_success {
+        ($parser:path, $input:literal, $result:expr) => {
+            assert_parse_success!($parser, $input, $result, "")
+        };
+        ($parser:path, $input:literal, $result:expr, $rest:literal) => {
+            let mut input = $input;
+            assert_eq!($parser(&mut input), Ok($result));
+            assert_eq!(input, $rest);
+        };
+    }
+
+    macro_rules! assert_parse_error {
+        ($parser:path, $input:literal) => {
+            let mut input = $input;
+            assert!($parser(&mut input).is_err());
+        };
+    }
+
+    fn object(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect()
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_parse_success!(json_string, r#""""#, String::new());
+        assert_parse_success!(json_string, r#""invalid_token""#, "invalid_token".to_owned());
+        assert_parse_success!(json_string, r#""a" : "b""#, "a".to_owned(), r#" : "b""#);
+        assert_parse_error!(json_string, r#""unterminated"#);
+        assert_parse_error!(json_string, "invalid_token");
+    }
+
+    #[test]
+    fn test_json_escape() {
+        assert_parse_success!(
+            json_string,
+            r#""\"\\\/\b\f\n\r\t""#,
+            "\"\\/\u{8}\u{c}\n\r\t".to_owned()
+        );
+        assert_parse_success!(
+            json_string,
+            r#""\u0061\u0041\u00e9\u20AC""#,
+            "aA\u{e9}\u{20ac}".to_owned()
+        );
+        assert_parse_error!(json_string, r#""\x""#);
+        assert_parse_error!(json_string, r#""\u00g0""#);
+        assert_parse_error!(json_string, r#""\u00""#);
+    }
+
+    #[test]
+    fn test_json_surrogate_pair() {
+        assert_parse_success!(json_string, r#""\ud83d\ude00""#, "\u{1f600}".to_owned());
+        assert_parse_success!(json_string, r#""\uD834\uDD1E""#, "\u{1d11e}".to_owned());
+        // unpaired surrogates are not valid characters
+        assert_parse_error!(json_string, r#""\ud83d""#);
+        assert_parse_error!(json_string, r#""\ude00""#);
+        assert_parse_error!(json_string, r#""\ud83dA""#);
+    }
+
+    #[test]
+    fn test_json_object() {
+        assert_parse_success!(json_object, "{}", Vec::new());
+        assert_parse_success!(
+            json_object,
+            r#"{"status":"invalid_token","scope":"sieve","openid-configuration":"https://example.com/.well-known/openid-configuration"}"#,
+            object(&[
+                ("status", "invalid_token"),
+                ("scope", "sieve"),
+                ("openid-configuration", "https://example.com/.well-known/openid-configuration"),
+            ])
+        );
+        assert_parse_success!(
+            json_object,
+            "{ \"status\" : \"401\" ,\n  \"scope\": \"a b\" }\n",
+            object(&[("status", "401"), ("scope", "a b")])
+        );
+        assert_parse_error!(json_object, r#"{"status":"401""#);
+        assert_parse_error!(json_object, r#"{"status"}"#);
+        assert_parse_error!(json_object, r#"["status"]"#);
+    }
+
+    #[test]
+    fn test_json_non_string_values() {
+        assert_parse_success!(
+            json_object,
+            r#"{"status":401,"retry":true,"scope":null,"ratio":-1.5e3}"#,
+            object(&[
+                ("status", "401"),
+                ("retry", "true"),
+                ("scope", "null"),
+                ("ratio", "-1.5e3")
+            ])
+        );
+        // nested values are not supported
+        assert_parse_error!(json_object, r#"{"scope":["sieve"]}"#);
+        assert_parse_error!(json_object, r#"{"scope":{"a":"b"}}"#);
+    }
+}
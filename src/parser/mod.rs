@@ -1,5 +1,6 @@
 use crate::{ResponseInfo, Version};
 
+pub(crate) mod json;
 pub(crate) mod responses;
 
 macro_rules! tag_variant {
@@ -10,6 +10,7 @@ use winnow::binary::length_take;
 use winnow::combinator::{
     alt, cut_err, delimited, opt, preceded, repeat, separated, separated_pair, terminated,
 };
+use winnow::error::StrContext;
 use winnow::token::take_while;
 use winnow::{ascii, BStr, ModalResult as PResult, Parser, Partial};
 
@@ -40,10 +41,19 @@ fn literal_s2c(input: Input) -> PResult<String> {
         .parse_next(input)
 }
 
+fn literal_s2c_bytes(input: Input) -> PResult<Vec<u8>> {
+    length_take(literal_s2c_len).map(ToOwned::to_owned).parse_next(input)
+}
+
 pub fn sievestring_s2c(input: Input) -> PResult<String> {
     alt((literal_s2c, quoted_string)).parse_next(input)
 }
 
+// Scripts are not required to be valid UTF-8, so they must not be rejected while parsing.
+pub fn sievestring_s2c_bytes(input: Input) -> PResult<Vec<u8>> {
+    alt((literal_s2c_bytes, quoted_string.map(String::into_bytes))).parse_next(input)
+}
+
 fn extension_data(input: Input) -> PResult<Vec<ExtensionItem>> {
     separated(1.., extension_item, space1).parse_next(input)
 }
@@ -67,15 +77,18 @@ fn code(input: Input) -> PResult<ResponseCode> {
             Caseless("QUOTA/MAXSCRIPTS").value(ResponseCode::Quota(Quota::MaxScripts)),
             Caseless("QUOTA/MAXSIZE").value(ResponseCode::Quota(Quota::MaxSize)),
             Caseless("QUOTA").value(ResponseCode::Quota(Quota::Unspecified)),
-            (Caseless("SASL"), sievestring_s2c).map(|(_, sasl)| ResponseCode::Sasl(sasl)),
-            (Caseless("REFERRAL"), sievestring_s2c).map(|(_, url)| ResponseCode::Referral(url)),
+            (Caseless("SASL"), preceded(space1, sievestring_s2c))
+                .map(|(_, sasl)| ResponseCode::Sasl(sasl)),
+            (Caseless("REFERRAL"), preceded(space1, sievestring_s2c))
+                .map(|(_, url)| ResponseCode::Referral(url)),
             Caseless("TRANSITION-NEEDED").value(ResponseCode::TransitionNeeded),
             Caseless("TRYLATER").value(ResponseCode::TryLater),
             Caseless("ACTIVE").value(ResponseCode::Active),
             Caseless("NONEXISTENT").value(ResponseCode::Nonexistent),
             Caseless("ALREADYEXISTS").value(ResponseCode::AlreadyExists),
             Caseless("WARNINGS").value(ResponseCode::Warnings),
-            (Caseless("TAG"), sievestring_s2c).map(|(_, tag)| ResponseCode::Tag(tag)),
+            (Caseless("TAG"), preceded(space1, sievestring_s2c))
+                .map(|(_, tag)| ResponseCode::Tag(tag)),
             (sievestring_s2c, opt(preceded(space1, extension_data)))
                 .map(|(name, data)| ResponseCode::Extension { name, data }),
         )),
@@ -149,6 +162,7 @@ pub fn response_ok(input: Input) -> PResult<Response<tag::Ok, Infallible, Infall
         tag: Tag::ok(),
         info: ResponseInfo { code, human },
     })
+    .context(StrContext::Label("OK response"))
     .parse_next(input)
 }
 
@@ -165,6 +179,7 @@ pub fn response_nobye(input: Input) -> PResult<Response<Infallible, tag::No, tag
         tag: oknobye,
         info: ResponseInfo { code, human },
     })
+    .context(StrContext::Label("NO or BYE response"))
     .parse_next(input)
 }
 
@@ -182,6 +197,7 @@ pub fn response_oknobye(input: Input) -> PResult<Response<tag::Ok, tag::No, tag:
             info: r.info,
         }),
     ))
+    .context(StrContext::Label("OK, NO or BYE response"))
     .parse_next(input)
 }
 
@@ -263,7 +279,9 @@ fn single_capability(input: Input) -> PResult<Capability> {
 pub fn response_capability(
     input: Input,
 ) -> PResult<(Vec<Capability>, Response<tag::Ok, tag::No, tag::Bye>)> {
-    (repeat(0.., single_capability), response_oknobye).parse_next(input)
+    (repeat(0.., single_capability), response_oknobye)
+        .context(StrContext::Label("capability listing"))
+        .parse_next(input)
 }
 
 pub fn response_authenticate(
@@ -273,6 +291,7 @@ pub fn response_authenticate(
         terminated(sievestring_s2c, crlf).map(Either::Left),
         response_oknobye.map(Either::Right),
     ))
+    .context(StrContext::Label("SASL challenge or response"))
     .parse_next(input)
 }
 
@@ -281,17 +300,44 @@ pub fn response_getscript(
     input: Input,
 ) -> PResult<
     Either<
-        (String, Response<tag::Ok, Infallible, Infallible>),
+        (Vec<u8>, Response<tag::Ok, Infallible, Infallible>),
         Response<Infallible, tag::No, tag::Bye>,
     >,
 > {
     alt((
-        separated_pair(sievestring_s2c, crlf, response_ok).map(Either::Left),
+        separated_pair(sievestring_s2c_bytes, crlf, response_ok).map(Either::Left),
         response_nobye.map(Either::Right),
     ))
+    .context(StrContext::Label("script or response"))
     .parse_next(input)
 }
 
+// Start of a `GETSCRIPT` response; a literal's content is not consumed so it can be streamed.
+#[allow(clippy::type_complexity)]
+pub fn response_getscript_start(
+    input: Input,
+) -> PResult<
+    Either<
+        Either<u64, (Vec<u8>, Response<tag::Ok, Infallible, Infallible>)>,
+        Response<Infallible, tag::No, tag::Bye>,
+    >,
+> {
+    alt((
+        literal_s2c_len.map(|len| Either::Left(Either::Left(len))),
+        separated_pair(quoted_string, crlf, response_ok)
+            .map(|(script, response)| Either::Left(Either::Right((script.into_bytes(), response)))),
+        response_nobye.map(Either::Right),
+    ))
+    .context(StrContext::Label("script or response"))
+    .parse_next(input)
+}
+
+pub fn response_getscript_end(input: Input) -> PResult<Response<tag::Ok, Infallible, Infallible>> {
+    preceded(crlf, response_ok)
+        .context(StrContext::Label("end of script"))
+        .parse_next(input)
+}
+
 #[allow(clippy::type_complexity)]
 pub fn response_listscripts(
     input: Input,
@@ -309,6 +355,7 @@ pub fn response_listscripts(
         ),
         response_oknobye,
     )
+        .context(StrContext::Label("script listing"))
         .parse_next(input)
 }
 
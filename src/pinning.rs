@@ -0,0 +1,551 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::{fs, io, process};
+
+use futures_rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use futures_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use futures_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use futures_rustls::rustls::server::ParsedCertificate;
+use futures_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error, OtherError, SignatureScheme,
+};
+
+/// SHA-256 digest of a certificate or of its public key (SPKI).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    pub fn of_certificate(certificate: &CertificateDer<'_>) -> Self {
+        Self::of(certificate)
+    }
+
+    pub fn of_public_key(certificate: &CertificateDer<'_>) -> Result<Self, Error> {
+        let certificate = ParsedCertificate::try_from(certificate)?;
+        Ok(Self::of(&certificate.subject_public_key_info()))
+    }
+
+    fn of(data: &[u8]) -> Self {
+        let digest = ::ring::digest::digest(&::ring::digest::SHA256, data);
+        Fingerprint(digest.as_ref().try_into().expect("SHA-256 digests are 32 bytes long"))
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Fingerprint({self})")
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+#[error("invalid SHA-256 fingerprint, expected 32 hex encoded bytes")]
+pub struct InvalidFingerprint;
+
+impl FromStr for Fingerprint {
+    type Err = InvalidFingerprint;
+
+    // accepts hex digits optionally separated by colons, as printed by `openssl x509 -fingerprint`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let separated = s.len() == 95 && s.bytes().skip(2).step_by(3).all(|b| b == b':');
+        let digits: Vec<u8> = s.bytes().filter(|&b| b != b':').collect();
+        if !(s.len() == 64 || separated)
+            || digits.len() != 64
+            || !digits.iter().all(u8::is_ascii_hexdigit)
+        {
+            return Err(InvalidFingerprint);
+        }
+
+        let mut fingerprint = [0; 32];
+        for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| InvalidFingerprint)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| InvalidFingerprint)?;
+        }
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificatePin {
+    /// fingerprint of the whole server certificate
+    Certificate(Fingerprint),
+    /// fingerprint of the public key of the server certificate, which stays the same when a
+    /// certificate is renewed with the same key
+    PublicKey(Fingerprint),
+}
+
+impl CertificatePin {
+    fn matches(&self, certificate: &CertificateDer<'_>) -> Result<bool, Error> {
+        Ok(match self {
+            CertificatePin::Certificate(pin) => *pin == Fingerprint::of_certificate(certificate),
+            CertificatePin::PublicKey(pin) => *pin == Fingerprint::of_public_key(certificate)?,
+        })
+    }
+}
+
+/// The server certificate does not match the pinned or previously seen fingerprint.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub struct CertificateMismatch {
+    pub server: String,
+    /// fingerprint of the certificate presented by the server
+    pub fingerprint: Fingerprint,
+    /// fingerprint remembered by a [`FingerprintStore`], `None` for pinned certificates
+    pub known: Option<Fingerprint>,
+}
+
+impl Display for CertificateMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let CertificateMismatch {
+            server,
+            fingerprint,
+            known,
+        } = self;
+        write!(f, "certificate of `{server}` with fingerprint {fingerprint} does not match ")?;
+        match known {
+            Some(known) => write!(f, "the previously seen fingerprint {known}"),
+            None => write!(f, "any pinned fingerprint"),
+        }
+    }
+}
+
+impl From<CertificateMismatch> for Error {
+    fn from(mismatch: CertificateMismatch) -> Self {
+        Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(mismatch))))
+    }
+}
+
+impl CertificateMismatch {
+    // recovers the mismatch from the error returned by the TLS handshake
+    pub(crate) fn from_io_error(error: &io::Error) -> Option<&Self> {
+        match error.get_ref()?.downcast_ref::<Error>()? {
+            Error::InvalidCertificate(CertificateError::Other(OtherError(error))) => {
+                error.downcast_ref()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Accepts only server certificates matching one of the pins, without checking the issuer or the
+/// server name. Suited for servers with self-signed certificates.
+#[derive(Debug)]
+pub struct PinnedVerifier {
+    pins: Vec<CertificatePin>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedVerifier {
+    pub fn new(pins: impl IntoIterator<Item = CertificatePin>) -> Self {
+        PinnedVerifier {
+            pins: pins.into_iter().collect(),
+            provider: Arc::new(ring::default_provider()),
+        }
+    }
+
+    pub fn into_config(self) -> Arc<ClientConfig> {
+        let provider = self.provider.clone();
+        client_config(provider, self)
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        for pin in &self.pins {
+            if pin.matches(end_entity)? {
+                return Ok(ServerCertVerified::assertion());
+            }
+        }
+
+        Err(CertificateMismatch {
+            server: server_name.to_str().into_owned(),
+            fingerprint: Fingerprint::of_certificate(end_entity),
+            known: None,
+        }
+        .into())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Remembers the certificate fingerprint of each server for [`TofuVerifier`].
+pub trait FingerprintStore: Debug + Send + Sync {
+    fn get(&self, server: &str) -> io::Result<Option<Fingerprint>>;
+
+    fn insert(&self, server: &str, fingerprint: Fingerprint) -> io::Result<()>;
+}
+
+/// Stores fingerprints in a text file with one `<server> <fingerprint>` entry per line.
+#[derive(Debug)]
+pub struct FileFingerprintStore {
+    path: PathBuf,
+    // serializes read-modify-write cycles of the file within this process
+    lock: Mutex<()>,
+}
+
+impl FileFingerprintStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileFingerprintStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> io::Result<BTreeMap<String, Fingerprint>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut entries = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid entry in line {} of `{}`", i + 1, self.path.display()),
+                )
+            };
+            let (server, fingerprint) = line.trim().split_once(' ').ok_or_else(invalid)?;
+            let fingerprint = fingerprint.trim().parse().map_err(|_| invalid())?;
+            entries.insert(server.to_string(), fingerprint);
+        }
+        Ok(entries)
+    }
+
+    // Replaces the file atomically, so a crash while writing cannot leave it truncated and make
+    // every server look like it is seen for the first time.
+    fn write(&self, content: &str) -> io::Result<()> {
+        let mut temp_name = self
+            .path
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`{}` is not a file name", self.path.display()),
+                )
+            })?
+            .to_owned();
+        temp_name.push(format!(".{}.tmp", process::id()));
+        let temp_path = self.path.with_file_name(temp_name);
+
+        let res = (|| {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, &self.path)
+        })();
+        if res.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        res
+    }
+}
+
+impl FingerprintStore for FileFingerprintStore {
+    fn get(&self, server: &str) -> io::Result<Option<Fingerprint>> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(self.read()?.get(server).copied())
+    }
+
+    fn insert(&self, server: &str, fingerprint: Fingerprint) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut entries = self.read()?;
+        entries.insert(server.to_string(), fingerprint);
+
+        let content: String = entries
+            .iter()
+            .map(|(server, fingerprint)| format!("{server} {fingerprint}\n"))
+            .collect();
+        self.write(&content)
+    }
+}
+
+/// Trust on first use: accepts any certificate from a server not seen before and remembers its
+/// fingerprint, later connections only accept a certificate with the same fingerprint.
+#[derive(Debug)]
+pub struct TofuVerifier {
+    store: Arc<dyn FingerprintStore>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl TofuVerifier {
+    pub fn new(store: Arc<dyn FingerprintStore>) -> Self {
+        TofuVerifier {
+            store,
+            provider: Arc::new(ring::default_provider()),
+        }
+    }
+
+    pub fn into_config(self) -> Arc<ClientConfig> {
+        let provider = self.provider.clone();
+        client_config(provider, self)
+    }
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let server = server_name.to_str();
+        let fingerprint = Fingerprint::of_certificate(end_entity);
+        let store_error = |error: io::Error| Error::General(format!("fingerprint store: {error}"));
+
+        match self.store.get(&server).map_err(store_error)? {
+            Some(known) if known == fingerprint => {}
+            Some(known) => {
+                return Err(CertificateMismatch {
+                    server: server.into_owned(),
+                    fingerprint,
+                    known: Some(known),
+                }
+                .into());
+            }
+            None => self.store.insert(&server, fingerprint).map_err(store_error)?,
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn client_config(
+    provider: Arc<CryptoProvider>,
+    verifier: impl ServerCertVerifier + 'static,
+) -> Arc<ClientConfig> {
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const CERTIFICATE: &[u8] = include_bytes!("../testdata/rsa-sha256.der");
+    // the same key as `CERTIFICATE` in a new certificate
+    const RENEWED: &[u8] = include_bytes!("../testdata/rsa-renewed.der");
+
+    const SEPARATED: &str = "5E:FF:56:A2:AF:15:88:25:35:29:B7:2F:4E:D5:F4:3C:\
+                             18:B4:6E:34:A6:9B:0F:57:A8:CE:C1:9A:29:A9:D2:71";
+
+    fn expected() -> Fingerprint {
+        Fingerprint([
+            0x5e, 0xff, 0x56, 0xa2, 0xaf, 0x15, 0x88, 0x25, 0x35, 0x29, 0xb7, 0x2f, 0x4e, 0xd5,
+            0xf4, 0x3c, 0x18, 0xb4, 0x6e, 0x34, 0xa6, 0x9b, 0x0f, 0x57, 0xa8, 0xce, 0xc1, 0x9a,
+            0x29, 0xa9, 0xd2, 0x71,
+        ])
+    }
+
+    #[test]
+    fn test_parse_fingerprint() {
+        assert_eq!(SEPARATED.parse(), Ok(expected()));
+        assert_eq!(SEPARATED.to_lowercase().parse(), Ok(expected()));
+        assert_eq!(SEPARATED.replace(':', "").parse(), Ok(expected()));
+        assert_eq!(expected().to_string(), SEPARATED);
+    }
+
+    #[test]
+    fn test_parse_invalid_fingerprint() {
+        let digits = SEPARATED.replace(':', "");
+        for invalid in [
+            "".to_owned(),
+            digits[..62].to_owned(),
+            format!("{digits}00"),
+            format!("{SEPARATED}:00"),
+            // SHA-1 fingerprint
+            SEPARATED[..59].to_owned(),
+            digits.replacen('5', "g", 1),
+            digits.replacen("5E", "+E", 1),
+            digits.replacen("5E", " 5E", 1),
+            SEPARATED.replacen(':', "", 1),
+            format!(":{}", &SEPARATED[..94]),
+            format!("{}:", &digits[..63]),
+        ] {
+            assert_eq!(invalid.parse::<Fingerprint>(), Err(InvalidFingerprint), "{invalid}");
+        }
+    }
+
+    fn verify(
+        verifier: &impl ServerCertVerifier,
+        certificate: &[u8],
+    ) -> Result<ServerCertVerified, Error> {
+        let server_name = ServerName::try_from("sieve.example.com").unwrap();
+        let certificate = CertificateDer::from(certificate);
+        verifier.verify_server_cert(&certificate, &[], &server_name, &[], UnixTime::now())
+    }
+
+    fn mismatch(error: Error) -> CertificateMismatch {
+        match error {
+            Error::InvalidCertificate(CertificateError::Other(OtherError(error))) => {
+                error.downcast_ref::<CertificateMismatch>().unwrap().clone()
+            }
+            error => panic!("unexpected error {error:?}"),
+        }
+    }
+
+    // store in a new file in the temporary directory
+    fn temp_store(name: &str) -> (PathBuf, FileFingerprintStore) {
+        let path = env::temp_dir().join(format!("managesieve-{}-{name}", process::id()));
+        let _ = fs::remove_file(&path);
+        (path.clone(), FileFingerprintStore::new(path))
+    }
+
+    #[test]
+    fn test_pinned_certificate() {
+        let pin = CertificatePin::Certificate(Fingerprint::of_certificate(&CERTIFICATE.into()));
+        let verifier = PinnedVerifier::new([pin]);
+        assert!(verify(&verifier, CERTIFICATE).is_ok());
+
+        let mismatch = mismatch(verify(&verifier, RENEWED).unwrap_err());
+        assert_eq!(mismatch.server, "sieve.example.com");
+        assert_eq!(mismatch.fingerprint, Fingerprint::of_certificate(&RENEWED.into()));
+        assert_eq!(mismatch.known, None);
+    }
+
+    #[test]
+    fn test_pinned_public_key() {
+        let fingerprint = Fingerprint::of_public_key(&CERTIFICATE.into()).unwrap();
+        let verifier = PinnedVerifier::new([CertificatePin::PublicKey(fingerprint)]);
+        assert!(verify(&verifier, CERTIFICATE).is_ok());
+        assert!(verify(&verifier, RENEWED).is_ok());
+
+        let verifier = PinnedVerifier::new([CertificatePin::PublicKey(expected())]);
+        let mismatch = mismatch(verify(&verifier, CERTIFICATE).unwrap_err());
+        assert_eq!(mismatch.fingerprint, Fingerprint::of_certificate(&CERTIFICATE.into()));
+    }
+
+    #[test]
+    fn test_tofu() {
+        let (path, store) = temp_store("tofu");
+        let store = Arc::new(store);
+        let verifier = TofuVerifier::new(store.clone());
+
+        // first use records the fingerprint
+        assert!(verify(&verifier, CERTIFICATE).is_ok());
+        let known = Fingerprint::of_certificate(&CERTIFICATE.into());
+        assert_eq!(store.get("sieve.example.com").unwrap(), Some(known));
+        assert!(verify(&verifier, CERTIFICATE).is_ok());
+
+        let mismatch = mismatch(verify(&verifier, RENEWED).unwrap_err());
+        assert_eq!(mismatch.server, "sieve.example.com");
+        assert_eq!(mismatch.fingerprint, Fingerprint::of_certificate(&RENEWED.into()));
+        assert_eq!(mismatch.known, Some(known));
+        // the rejected certificate does not replace the known one
+        assert_eq!(store.get("sieve.example.com").unwrap(), Some(known));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let (path, store) = temp_store("round-trip");
+        assert_eq!(store.get("sieve.example.com").unwrap(), None);
+
+        let other = Fingerprint([0xab; 32]);
+        store.insert("sieve.example.com", expected()).unwrap();
+        store.insert("other.example.com", other).unwrap();
+
+        // a new store reads the entries from the file
+        let store = FileFingerprintStore::new(&path);
+        assert_eq!(store.get("sieve.example.com").unwrap(), Some(expected()));
+        assert_eq!(store.get("other.example.com").unwrap(), Some(other));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("other.example.com {other}\nsieve.example.com {SEPARATED}\n")
+        );
+
+        // no temporary file is left behind
+        let temp_files = fs::read_dir(env::temp_dir())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(&format!("managesieve-{}-round-trip.", process::id()))
+            })
+            .count();
+        assert_eq!(temp_files, 0);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CredentialsError {
+    #[error("{0} must not contain NUL characters")]
+    Nul(&'static str),
+
+    #[error("{0} must not be empty")]
+    Empty(&'static str),
+
+    #[error("{0} contains invalid characters")]
+    InvalidCharacters(&'static str),
+
+    #[error("{field} is rejected by SASLprep: {error}")]
+    SaslPrep {
+        field: &'static str,
+        #[source]
+        error: stringprep::Error,
+    },
+}
+
+pub(crate) fn check_nul(field: &'static str, value: &str) -> Result<(), CredentialsError> {
+    if value.contains('\0') {
+        return Err(CredentialsError::Nul(field));
+    }
+    Ok(())
+}
+
+// SASLprep (RFC 4013) for user names and passwords
+pub(crate) fn prepare<'a>(
+    field: &'static str,
+    value: &'a str,
+) -> Result<Cow<'a, str>, CredentialsError> {
+    check_nul(field, value)?;
+    let prepared =
+        stringprep::saslprep(value).map_err(|error| CredentialsError::SaslPrep { field, error })?;
+    if prepared.is_empty() {
+        return Err(CredentialsError::Empty(field));
+    }
+    Ok(prepared)
+}
@@ -8,6 +8,16 @@ use std::pin::Pin;
 use pin_project_lite::pin_project;
 use thiserror::Error;
 
+mod credentials;
+mod oauth;
+mod plain;
+mod scram;
+
+pub use credentials::CredentialsError;
+pub use oauth::{OAuthBearer, OAuthError, XOAuth2};
+pub use plain::{Plain, PlainError};
+pub use scram::{Scram, ScramError, ScramHash};
+
 #[derive(Error, Debug)]
 pub enum SaslError<E> {
     #[error("authentication is not completed, but the server sent `OK` response")]
@@ -37,6 +47,9 @@ pub enum SaslError<E> {
     )]
     TransitionNeeded,
 
+    #[error("the server referred the client to `{url}`")]
+    Referral { url: String },
+
     #[error(fmt = fmt_other)]
     Other { message: Option<String> },
 }
@@ -83,8 +96,15 @@ pub enum InitialSaslState<'a> {
 pub trait Sasl<'a> {
     type Error;
     fn name(&self) -> &'static str;
-    fn init(&self) -> InitialSaslState<'a>;
+    fn init(&self) -> InitialSaslState<'_>;
     fn resume(self: Pin<&mut Self>, arg: Vec<u8>) -> Result<SaslState, Self::Error>;
+
+    /// Handles a challenge received after the client completed the exchange, which mechanisms like
+    /// `OAUTHBEARER` use to report errors. Returns the error and the response the server expects
+    /// before it fails the authentication, `None` cancels the exchange.
+    fn error_challenge(self: Pin<&mut Self>, _arg: Vec<u8>) -> Option<(Self::Error, Vec<u8>)> {
+        None
+    }
 }
 
 impl<'a, E> Sasl<'a> for Pin<Box<dyn Sasl<'a, Error = E>>> {
@@ -94,13 +114,17 @@ impl<'a, E> Sasl<'a> for Pin<Box<dyn Sasl<'a, Error = E>>> {
         self.deref().name()
     }
 
-    fn init(&self) -> InitialSaslState<'a> {
+    fn init(&self) -> InitialSaslState<'_> {
         self.deref().init()
     }
 
     fn resume(self: Pin<&mut Self>, arg: Vec<u8>) -> Result<SaslState, Self::Error> {
         self.as_deref_mut().resume(arg)
     }
+
+    fn error_challenge(self: Pin<&mut Self>, arg: Vec<u8>) -> Option<(Self::Error, Vec<u8>)> {
+        self.as_deref_mut().error_challenge(arg)
+    }
 }
 
 impl<'a> Sasl<'a> for (&'static str, &'a [u8]) {
@@ -110,7 +134,7 @@ impl<'a> Sasl<'a> for (&'static str, &'a [u8]) {
         self.0
     }
 
-    fn init(&self) -> InitialSaslState<'a> {
+    fn init(&self) -> InitialSaslState<'_> {
         InitialSaslState::Complete(self.1)
     }
 
@@ -135,7 +159,7 @@ impl<'a, F: FnMut(Vec<u8>) -> Result<SaslState, E>, E> Sasl<'a> for SaslFn<'a, F
         self.name
     }
 
-    fn init(&self) -> InitialSaslState<'a> {
+    fn init(&self) -> InitialSaslState<'_> {
         match self.init {
             None => InitialSaslState::None,
             Some(i) => InitialSaslState::Yielded(i),
@@ -167,7 +191,7 @@ impl<'a, C: Coroutine<Vec<u8>, Return = Result<Option<Vec<u8>>, E>, Yield = Vec<
         self.name
     }
 
-    fn init(&self) -> InitialSaslState<'a> {
+    fn init(&self) -> InitialSaslState<'_> {
         match self.init {
             None => InitialSaslState::None,
             Some(i) => InitialSaslState::Yielded(i),
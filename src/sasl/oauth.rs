@@ -0,0 +1,261 @@
+use std::fmt::{Debug, Formatter};
+use std::pin::Pin;
+
+use thiserror::Error;
+use winnow::Parser;
+
+use crate::parser::json::json_object;
+use crate::sasl::credentials::check_nul;
+use crate::sasl::{CredentialsError, InitialSaslState, Sasl, SaslState};
+
+/// Error reported by the server in the JSON challenge of a failed OAuth authentication.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum OAuthError {
+    #[error(fmt = fmt_rejected)]
+    Rejected {
+        status: String,
+        /// scope the token needs
+        scope: Option<String>,
+        /// URL of the OpenID Connect discovery document of the authorization server
+        openid_configuration: Option<String>,
+    },
+
+    #[error("the server sent an invalid error challenge: {0:?}")]
+    InvalidChallenge(String),
+
+    #[error("the server sent a challenge after the OAuth exchange completed")]
+    UnexpectedChallenge,
+}
+
+fn fmt_rejected(
+    status: &String,
+    scope: &Option<String>,
+    _openid_configuration: &Option<String>,
+    formatter: &mut Formatter,
+) -> std::fmt::Result {
+    write!(formatter, "the server rejected the token with status `{status}`")?;
+    if let Some(scope) = scope {
+        write!(formatter, ", required scope is `{scope}`")?;
+    }
+    Ok(())
+}
+
+impl OAuthError {
+    fn from_challenge(challenge: &[u8]) -> Self {
+        let challenge = String::from_utf8_lossy(challenge);
+        let Ok(fields) = json_object.parse(&challenge) else {
+            return OAuthError::InvalidChallenge(challenge.into_owned());
+        };
+
+        let field = |name: &str| {
+            fields.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.clone())
+        };
+        match field("status") {
+            Some(status) => OAuthError::Rejected {
+                status,
+                scope: field("scope"),
+                openid_configuration: field("openid-configuration"),
+            },
+            None => OAuthError::InvalidChallenge(challenge.into_owned()),
+        }
+    }
+}
+
+// RFC 6750: b64token = 1*( ALPHA / DIGIT / "-" / "." / "_" / "~" / "+" / "/" ) *"="
+fn check_token(token: &str) -> Result<(), CredentialsError> {
+    let token = token.trim_end_matches('=');
+    if token.is_empty() {
+        return Err(CredentialsError::Empty("token"));
+    }
+    if !token.bytes().all(|c| c.is_ascii_alphanumeric() || b"-._~+/".contains(&c)) {
+        return Err(CredentialsError::InvalidCharacters("token"));
+    }
+    Ok(())
+}
+
+// values of key-value pairs are terminated by `\x01`
+fn check_value(field: &'static str, value: &str) -> Result<(), CredentialsError> {
+    check_nul(field, value)?;
+    if value.contains('\x01') {
+        return Err(CredentialsError::InvalidCharacters(field));
+    }
+    Ok(())
+}
+
+/// `OAUTHBEARER` mechanism (RFC 7628), authenticating with `&OAuthBearer`.
+///
+/// If the server rejects the token, authentication fails with an [`OAuthError`].
+#[derive(Clone)]
+pub struct OAuthBearer {
+    message: Vec<u8>,
+}
+
+impl OAuthBearer {
+    /// `authzid` is the user to act as, servers usually require it to be the owner of the token.
+    pub fn new(authzid: Option<&str>, token: &str) -> Result<Self, CredentialsError> {
+        Self::build(authzid, None, token)
+    }
+
+    /// Additionally sends the host name and port the client connected to.
+    pub fn with_server(
+        authzid: Option<&str>,
+        host: &str,
+        port: u16,
+        token: &str,
+    ) -> Result<Self, CredentialsError> {
+        Self::build(authzid, Some((host, port)), token)
+    }
+
+    fn build(
+        authzid: Option<&str>,
+        server: Option<(&str, u16)>,
+        token: &str,
+    ) -> Result<Self, CredentialsError> {
+        check_token(token)?;
+
+        let mut message = "n,".to_string();
+        if let Some(authzid) = authzid {
+            check_value("authorization identity", authzid)?;
+            message.push_str("a=");
+            message.push_str(&authzid.replace('=', "=3D").replace(',', "=2C"));
+        }
+        message.push_str(",\x01");
+        if let Some((host, port)) = server {
+            check_value("host", host)?;
+            message.push_str(&format!("host={host}\x01port={port}\x01"));
+        }
+        message.push_str(&format!("auth=Bearer {token}\x01\x01"));
+
+        Ok(OAuthBearer {
+            message: message.into_bytes(),
+        })
+    }
+}
+
+impl Debug for OAuthBearer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthBearer").finish_non_exhaustive()
+    }
+}
+
+impl<'a> Sasl<'a> for &'a OAuthBearer {
+    type Error = OAuthError;
+
+    fn name(&self) -> &'static str {
+        "OAUTHBEARER"
+    }
+
+    fn init(&self) -> InitialSaslState<'_> {
+        InitialSaslState::Complete(&self.message)
+    }
+
+    fn resume(self: Pin<&mut Self>, _arg: Vec<u8>) -> Result<SaslState, Self::Error> {
+        // the initial response completes the exchange, errors are passed to `error_challenge`
+        Err(OAuthError::UnexpectedChallenge)
+    }
+
+    fn error_challenge(self: Pin<&mut Self>, arg: Vec<u8>) -> Option<(Self::Error, Vec<u8>)> {
+        // RFC 7628, section 3.2.3: the client must send a dummy response to receive the `NO`
+        Some((OAuthError::from_challenge(&arg), b"\x01".to_vec()))
+    }
+}
+
+/// `XOAUTH2` mechanism used by Google and Microsoft, authenticating with `&XOAuth2`.
+///
+/// If the server rejects the token, authentication fails with an [`OAuthError`].
+#[derive(Clone)]
+pub struct XOAuth2 {
+    message: Vec<u8>,
+}
+
+impl XOAuth2 {
+    pub fn new(user: &str, token: &str) -> Result<Self, CredentialsError> {
+        check_value("user name", user)?;
+        check_token(token)?;
+
+        let message = format!("user={user}\x01auth=Bearer {token}\x01\x01");
+        Ok(XOAuth2 {
+            message: message.into_bytes(),
+        })
+    }
+}
+
+impl Debug for XOAuth2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XOAuth2").finish_non_exhaustive()
+    }
+}
+
+impl<'a> Sasl<'a> for &'a XOAuth2 {
+    type Error = OAuthError;
+
+    fn name(&self) -> &'static str {
+        "XOAUTH2"
+    }
+
+    fn init(&self) -> InitialSaslState<'_> {
+        InitialSaslState::Complete(&self.message)
+    }
+
+    fn resume(self: Pin<&mut Self>, _arg: Vec<u8>) -> Result<SaslState, Self::Error> {
+        // the initial response completes the exchange, errors are passed to `error_challenge`
+        Err(OAuthError::UnexpectedChallenge)
+    }
+
+    fn error_challenge(self: Pin<&mut Self>, arg: Vec<u8>) -> Option<(Self::Error, Vec<u8>)> {
+        // the server expects an empty response before it sends `NO`
+        Some((OAuthError::from_challenge(&arg), Vec::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::commands::Authenticate;
+    use crate::sasl::SaslError;
+    use crate::state::Unauthenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    #[test]
+    fn test_rejects_challenge() {
+        let bearer = OAuthBearer::new(None, "token").unwrap();
+        let mut sasl = &bearer;
+        let res = Pin::new(&mut sasl).resume(b"challenge".to_vec());
+        assert_eq!(res.err(), Some(OAuthError::UnexpectedChallenge));
+
+        let xoauth2 = XOAuth2::new("user", "token").unwrap();
+        let mut sasl = &xoauth2;
+        let res = Pin::new(&mut sasl).resume(b"challenge".to_vec());
+        assert_eq!(res.err(), Some(OAuthError::UnexpectedChallenge));
+    }
+
+    // the server answers the initial response with an error challenge instead of `OK`
+    #[test]
+    fn test_error_challenge() {
+        let stream = ScriptedStream::new(
+            &[
+                b"\"eyJzdGF0dXMiOiJpbnZhbGlkX3Rva2VuIn0=\"\r\n",
+                b"NO \"authentication failed\"\r\n",
+            ],
+            1024,
+        );
+        let bearer = OAuthBearer::new(None, "token").unwrap();
+
+        let res = block_on(connection::<Unauthenticated>(stream).authenticate(&bearer)).unwrap();
+        let Authenticate::Error {
+            connection: Some(connection),
+            error: SaslError::SaslError(error),
+        } = res
+        else {
+            panic!("the rejected token must be reported");
+        };
+        assert_eq!(error, OAuthError::Rejected {
+            status: "invalid_token".into(),
+            scope: None,
+            openid_configuration: None,
+        });
+        assert!(connection.stream.written.ends_with(b"\"AQ==\"\r\n"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Debug, Formatter};
+use std::pin::Pin;
+
+use thiserror::Error;
+
+use crate::sasl::credentials::{check_nul, prepare};
+use crate::sasl::{CredentialsError, InitialSaslState, Sasl, SaslState};
+
+/// `PLAIN` mechanism (RFC 4616), authenticating with `&Plain`.
+///
+/// The user name and password are prepared with SASLprep (RFC 4013). The authorization identity
+/// is sent as given, it is interpreted by the server.
+#[derive(Clone)]
+pub struct Plain {
+    message: Vec<u8>,
+}
+
+impl Plain {
+    pub fn new(username: &str, password: &str) -> Result<Self, CredentialsError> {
+        Self::build(None, username, password)
+    }
+
+    /// Authenticates as `username`, but acts as the user `authzid`.
+    ///
+    /// Unlike the user name, `authzid` is not prepared with SASLprep: RFC 4616 leaves its form to
+    /// the server, so it is only checked for NUL characters.
+    pub fn with_authzid(
+        authzid: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, CredentialsError> {
+        Self::build(Some(authzid), username, password)
+    }
+
+    fn build(
+        authzid: Option<&str>,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, CredentialsError> {
+        let authzid = authzid.unwrap_or_default();
+        check_nul("authorization identity", authzid)?;
+        let username = prepare("user name", username)?;
+        let password = prepare("password", password)?;
+
+        let mut message = Vec::with_capacity(authzid.len() + username.len() + password.len() + 2);
+        message.extend_from_slice(authzid.as_bytes());
+        message.push(0);
+        message.extend_from_slice(username.as_bytes());
+        message.push(0);
+        message.extend_from_slice(password.as_bytes());
+        Ok(Plain { message })
+    }
+}
+
+impl Debug for Plain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plain").finish_non_exhaustive()
+    }
+}
+
+/// Error of the `PLAIN` mechanism.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PlainError {
+    #[error("the server sent a challenge after the `PLAIN` exchange completed")]
+    UnexpectedChallenge,
+}
+
+impl<'a> Sasl<'a> for &'a Plain {
+    type Error = PlainError;
+
+    fn name(&self) -> &'static str {
+        "PLAIN"
+    }
+
+    fn init(&self) -> InitialSaslState<'_> {
+        InitialSaslState::Complete(&self.message)
+    }
+
+    fn resume(self: Pin<&mut Self>, _arg: Vec<u8>) -> Result<SaslState, Self::Error> {
+        // the initial response completes the exchange
+        Err(PlainError::UnexpectedChallenge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(plain: &Plain) -> Vec<u8> {
+        match (&plain).init() {
+            InitialSaslState::Complete(message) => message.to_vec(),
+            _ => panic!("`PLAIN` must complete with the initial response"),
+        }
+    }
+
+    #[test]
+    fn test_message_format() {
+        let plain = Plain::new("user", "pencil").unwrap();
+        assert_eq!(message(&plain), b"\0user\0pencil");
+
+        let plain = Plain::with_authzid("admin", "user", "pencil").unwrap();
+        assert_eq!(message(&plain), b"admin\0user\0pencil");
+    }
+
+    #[test]
+    fn test_saslprep_removes_soft_hyphen() {
+        let plain = Plain::new("us\u{AD}er", "pen\u{AD}cil").unwrap();
+        assert_eq!(message(&plain), b"\0user\0pencil");
+    }
+
+    #[test]
+    fn test_saslprep_normalizes_nfkc() {
+        // ROMAN NUMERAL FOUR and LATIN SMALL LIGATURE FI
+        let plain = Plain::new("\u{2163}", "\u{FB01}le").unwrap();
+        assert_eq!(message(&plain), b"\0IV\0file");
+    }
+
+    #[test]
+    fn test_authzid_is_not_prepared() {
+        let plain = Plain::with_authzid("ad\u{AD}min", "user", "pencil").unwrap();
+        assert_eq!(message(&plain), "ad\u{AD}min\0user\0pencil".as_bytes());
+    }
+
+    #[test]
+    fn test_rejects_nul() {
+        let error = Plain::with_authzid("ad\0min", "user", "pencil").unwrap_err();
+        assert!(matches!(error, CredentialsError::Nul("authorization identity")), "{error:?}");
+
+        let error = Plain::new("us\0er", "pencil").unwrap_err();
+        assert!(matches!(error, CredentialsError::Nul("user name")), "{error:?}");
+
+        let error = Plain::new("user", "pen\0cil").unwrap_err();
+        assert!(matches!(error, CredentialsError::Nul("password")), "{error:?}");
+    }
+
+    #[test]
+    fn test_rejects_empty_after_saslprep() {
+        let error = Plain::new("user", "\u{AD}").unwrap_err();
+        assert!(matches!(error, CredentialsError::Empty("password")), "{error:?}");
+    }
+
+    #[test]
+    fn test_rejects_challenge() {
+        let plain = Plain::new("user", "pencil").unwrap();
+        let mut sasl = &plain;
+        let res = Pin::new(&mut sasl).resume(b"challenge".to_vec());
+        assert_eq!(res.err(), Some(PlainError::UnexpectedChallenge));
+    }
+}
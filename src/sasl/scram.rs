@@ -0,0 +1,552 @@
+use std::fmt::{Debug, Formatter};
+use std::num::NonZeroU32;
+use std::pin::Pin;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use thiserror::Error;
+
+use crate::sasl::credentials::prepare;
+use crate::sasl::{CredentialsError, InitialSaslState, Sasl, SaslState};
+use crate::state::{Tls, Unauthenticated};
+use crate::{AsyncRead, AsyncWrite, ChannelBindingError, ChannelBindingType, Connection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScramHash {
+    Sha1,
+    Sha256,
+}
+
+impl ScramHash {
+    fn mechanism(self, plus: bool) -> &'static str {
+        match (self, plus) {
+            (ScramHash::Sha1, false) => "SCRAM-SHA-1",
+            (ScramHash::Sha1, true) => "SCRAM-SHA-1-PLUS",
+            (ScramHash::Sha256, false) => "SCRAM-SHA-256",
+            (ScramHash::Sha256, true) => "SCRAM-SHA-256-PLUS",
+        }
+    }
+
+    fn digest(self) -> &'static digest::Algorithm {
+        match self {
+            ScramHash::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            ScramHash::Sha256 => &digest::SHA256,
+        }
+    }
+
+    fn hmac(self) -> hmac::Algorithm {
+        match self {
+            ScramHash::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            ScramHash::Sha256 => hmac::HMAC_SHA256,
+        }
+    }
+
+    fn pbkdf2(self) -> pbkdf2::Algorithm {
+        match self {
+            ScramHash::Sha1 => pbkdf2::PBKDF2_HMAC_SHA1,
+            ScramHash::Sha256 => pbkdf2::PBKDF2_HMAC_SHA256,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ScramError {
+    #[error(transparent)]
+    Credentials(#[from] CredentialsError),
+
+    #[error(transparent)]
+    ChannelBinding(#[from] ChannelBindingError),
+
+    #[error("failed to generate a nonce")]
+    Random,
+
+    #[error("received invalid SCRAM message from the server")]
+    InvalidServerMessage,
+
+    #[error("the server nonce does not extend the client nonce")]
+    NonceMismatch,
+
+    #[error("the server requires the unsupported extension `{0}`")]
+    MandatoryExtension(String),
+
+    #[error("the server rejected the authentication: {0}")]
+    Server(String),
+
+    #[error("the server signature is invalid, the server does not know the password")]
+    InvalidServerSignature,
+
+    #[error("the authentication exchange is already completed")]
+    Completed,
+}
+
+// channel binding support announced in the GS2 header
+#[derive(Clone)]
+enum Gs2ChannelBinding {
+    /// `n`: the client does not support channel binding
+    Unsupported,
+    /// `y`: the client supports channel binding, but the server does not
+    NotAdvertised,
+    /// `p`: the client uses channel binding
+    Used(ChannelBindingType, Vec<u8>),
+}
+
+enum ScramState {
+    Initial,
+    ClientFinalSent {
+        server_key: hmac::Key,
+        auth_message: Vec<u8>,
+    },
+    Completed,
+}
+
+/// `SCRAM-SHA-1` and `SCRAM-SHA-256` mechanisms with their `-PLUS` variants (RFC 5802, RFC 7677).
+///
+/// A `Scram` authenticates only once, as every exchange needs a new nonce. Create another one to
+/// retry, or return a new one from the closure passed to
+/// [`Connection::authenticate_following_referrals`].
+///
+/// The final message of the server is verified, so a server which does not know the password
+/// fails the authentication with [`ScramError::InvalidServerSignature`].
+pub struct Scram {
+    hash: ScramHash,
+    password: String,
+    gs2_header: String,
+    channel_binding: Gs2ChannelBinding,
+    // `client-first-message`, its bare part starts after the GS2 header
+    client_first: Vec<u8>,
+    state: ScramState,
+}
+
+impl Scram {
+    /// Authenticates without channel binding.
+    pub fn new(hash: ScramHash, username: &str, password: &str) -> Result<Self, ScramError> {
+        Self::build(hash, None, username, password, Gs2ChannelBinding::Unsupported)
+    }
+
+    /// Authenticates as `username`, but acts as the user `authzid`.
+    pub fn with_authzid(
+        hash: ScramHash,
+        authzid: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, ScramError> {
+        Self::build(hash, Some(authzid), username, password, Gs2ChannelBinding::Unsupported)
+    }
+
+    /// Uses the `-PLUS` variant if the server offers it, binding the authentication to the TLS
+    /// connection with `tls-exporter` on TLS 1.3 and `tls-server-end-point` otherwise.
+    pub fn for_connection<STREAM: AsyncRead + AsyncWrite + Unpin>(
+        connection: &Connection<STREAM, Tls, Unauthenticated>,
+        hash: ScramHash,
+        authzid: Option<&str>,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, ScramError> {
+        let plus = hash.mechanism(true);
+        let channel_binding = if connection.capabilities().sasl.iter().any(|m| m == plus) {
+            let binding_type =
+                ChannelBindingType::for_protocol_version(connection.protocol_version());
+            let data = connection.channel_binding(binding_type)?;
+            Gs2ChannelBinding::Used(binding_type, data)
+        } else {
+            Gs2ChannelBinding::NotAdvertised
+        };
+        Self::build(hash, authzid, username, password, channel_binding)
+    }
+
+    /// Uses the `-PLUS` variant with channel binding data from
+    /// [`Connection::channel_binding`](crate::Connection::channel_binding).
+    pub fn with_channel_binding(
+        hash: ScramHash,
+        authzid: Option<&str>,
+        username: &str,
+        password: &str,
+        binding_type: ChannelBindingType,
+        data: Vec<u8>,
+    ) -> Result<Self, ScramError> {
+        let channel_binding = Gs2ChannelBinding::Used(binding_type, data);
+        Self::build(hash, authzid, username, password, channel_binding)
+    }
+
+    fn build(
+        hash: ScramHash,
+        authzid: Option<&str>,
+        username: &str,
+        password: &str,
+        channel_binding: Gs2ChannelBinding,
+    ) -> Result<Self, ScramError> {
+        let mut nonce = [0; 24];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| ScramError::Random)?;
+        let nonce = STANDARD.encode(nonce);
+
+        Self::build_with_nonce(hash, authzid, username, password, channel_binding, &nonce)
+    }
+
+    fn build_with_nonce(
+        hash: ScramHash,
+        authzid: Option<&str>,
+        username: &str,
+        password: &str,
+        channel_binding: Gs2ChannelBinding,
+        nonce: &str,
+    ) -> Result<Self, ScramError> {
+        let username = prepare("user name", username)?;
+        let password = prepare("password", password)?.into_owned();
+
+        let mut gs2_header = match &channel_binding {
+            Gs2ChannelBinding::Unsupported => "n,".to_string(),
+            Gs2ChannelBinding::NotAdvertised => "y,".to_string(),
+            Gs2ChannelBinding::Used(binding_type, _) => format!("p={},", binding_type.name()),
+        };
+        if let Some(authzid) = authzid {
+            gs2_header.push_str("a=");
+            gs2_header.push_str(&escape_name("authorization identity", authzid)?);
+        }
+        gs2_header.push(',');
+
+        let client_first =
+            format!("{gs2_header}n={},r={nonce}", escape_name("user name", &username)?);
+
+        Ok(Scram {
+            hash,
+            password,
+            gs2_header,
+            channel_binding,
+            client_first: client_first.into_bytes(),
+            state: ScramState::Initial,
+        })
+    }
+
+    fn client_first_bare(&self) -> &[u8] {
+        &self.client_first[self.gs2_header.len()..]
+    }
+
+    fn client_nonce(&self) -> &[u8] {
+        let bare = self.client_first_bare();
+        let start = bare.windows(3).rposition(|w| w == b",r=").expect("nonce is always present");
+        &bare[start + 3..]
+    }
+
+    // returns `client-final-message` and the state to verify the server signature
+    fn client_final(&self, server_first: &[u8]) -> Result<(Vec<u8>, ScramState), ScramError> {
+        let server_first_str =
+            std::str::from_utf8(server_first).map_err(|_| ScramError::InvalidServerMessage)?;
+        let mut attributes = server_first_str.split(',');
+
+        let mut next = |name: &str| {
+            let attribute = attributes.next().ok_or(ScramError::InvalidServerMessage)?;
+            if let Some(extension) = attribute.strip_prefix("m=") {
+                return Err(ScramError::MandatoryExtension(extension.to_string()));
+            }
+            attribute.strip_prefix(name).ok_or(ScramError::InvalidServerMessage)
+        };
+        let nonce = next("r=")?;
+        let salt = STANDARD.decode(next("s=")?).map_err(|_| ScramError::InvalidServerMessage)?;
+        let iterations: NonZeroU32 =
+            next("i=")?.parse().map_err(|_| ScramError::InvalidServerMessage)?;
+
+        if !nonce.as_bytes().starts_with(self.client_nonce())
+            || nonce.len() == self.client_nonce().len()
+        {
+            return Err(ScramError::NonceMismatch);
+        }
+
+        let mut channel_binding = self.gs2_header.as_bytes().to_vec();
+        if let Gs2ChannelBinding::Used(_, data) = &self.channel_binding {
+            channel_binding.extend_from_slice(data);
+        }
+        let client_final_without_proof =
+            format!("c={},r={nonce}", STANDARD.encode(channel_binding));
+
+        let mut salted_password = vec![0; self.hash.digest().output_len()];
+        pbkdf2::derive(
+            self.hash.pbkdf2(),
+            iterations,
+            &salt,
+            self.password.as_bytes(),
+            &mut salted_password,
+        );
+        let salted_password = hmac::Key::new(self.hash.hmac(), &salted_password);
+
+        let mut auth_message = self.client_first_bare().to_vec();
+        auth_message.push(b',');
+        auth_message.extend_from_slice(server_first);
+        auth_message.push(b',');
+        auth_message.extend_from_slice(client_final_without_proof.as_bytes());
+
+        let client_key = hmac::sign(&salted_password, b"Client Key");
+        let stored_key = digest::digest(self.hash.digest(), client_key.as_ref());
+        let stored_key = hmac::Key::new(self.hash.hmac(), stored_key.as_ref());
+        let client_signature = hmac::sign(&stored_key, &auth_message);
+        let client_proof: Vec<u8> = client_key
+            .as_ref()
+            .iter()
+            .zip(client_signature.as_ref())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+
+        let server_key = hmac::sign(&salted_password, b"Server Key");
+        let server_key = hmac::Key::new(self.hash.hmac(), server_key.as_ref());
+
+        let client_final =
+            format!("{client_final_without_proof},p={}", STANDARD.encode(client_proof));
+        let state = ScramState::ClientFinalSent {
+            server_key,
+            auth_message,
+        };
+        Ok((client_final.into_bytes(), state))
+    }
+}
+
+// `saslname` of RFC 5802, with `=` and `,` escaped
+fn escape_name(field: &'static str, name: &str) -> Result<String, CredentialsError> {
+    if name.contains('\0') {
+        return Err(CredentialsError::Nul(field));
+    }
+    Ok(name.replace('=', "=3D").replace(',', "=2C"))
+}
+
+fn verify_server_final(
+    server_final: &[u8],
+    server_key: &hmac::Key,
+    auth_message: &[u8],
+) -> Result<(), ScramError> {
+    let server_final =
+        std::str::from_utf8(server_final).map_err(|_| ScramError::InvalidServerMessage)?;
+    let verifier = server_final.split(',').next().unwrap_or_default();
+
+    if let Some(error) = verifier.strip_prefix("e=") {
+        return Err(ScramError::Server(error.to_string()));
+    }
+    let verifier = verifier.strip_prefix("v=").ok_or(ScramError::InvalidServerMessage)?;
+    let verifier = STANDARD.decode(verifier).map_err(|_| ScramError::InvalidServerMessage)?;
+
+    hmac::verify(server_key, auth_message, &verifier)
+        .map_err(|_| ScramError::InvalidServerSignature)
+}
+
+impl Debug for Scram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scram")
+            .field("hash", &self.hash)
+            .field("gs2_header", &self.gs2_header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Sasl<'a> for Scram {
+    type Error = ScramError;
+
+    fn name(&self) -> &'static str {
+        let plus = matches!(self.channel_binding, Gs2ChannelBinding::Used(..));
+        self.hash.mechanism(plus)
+    }
+
+    fn init(&self) -> InitialSaslState<'_> {
+        InitialSaslState::Yielded(&self.client_first)
+    }
+
+    fn resume(self: Pin<&mut Self>, arg: Vec<u8>) -> Result<SaslState, Self::Error> {
+        let this = self.get_mut();
+        match &this.state {
+            ScramState::Initial => {
+                let (client_final, next_state) = this.client_final(&arg)?;
+                this.state = next_state;
+                Ok(SaslState::Yielded(client_final))
+            }
+            ScramState::ClientFinalSent {
+                server_key,
+                auth_message,
+            } => {
+                verify_server_final(&arg, server_key, auth_message)?;
+                this.state = ScramState::Completed;
+                Ok(SaslState::Complete)
+            }
+            ScramState::Completed => Err(ScramError::Completed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::commands::{Authenticate, Referrals};
+    use crate::sasl::SaslError;
+    use crate::state::Unauthenticated;
+    use crate::test_util::{connection, ScriptedStream};
+
+    const SHA256_NONCE: &str = "rOprNGfwEbeRWgbNEkqO";
+    const SHA256_SERVER_FIRST: &str =
+        "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    const SHA256_SERVER_FINAL: &str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+    fn scram(hash: ScramHash, channel_binding: Gs2ChannelBinding, nonce: &str) -> Scram {
+        Scram::build_with_nonce(hash, None, "user", "pencil", channel_binding, nonce).unwrap()
+    }
+
+    fn exchange(
+        hash: ScramHash,
+        nonce: &str,
+        server_first: &str,
+        server_final: &str,
+    ) -> (String, String, Result<SaslState, ScramError>) {
+        exchange_with(
+            scram(hash, Gs2ChannelBinding::Unsupported, nonce),
+            server_first,
+            server_final,
+        )
+    }
+
+    fn exchange_with(
+        scram: Scram,
+        server_first: &str,
+        server_final: &str,
+    ) -> (String, String, Result<SaslState, ScramError>) {
+        let mut sasl = pin!(scram);
+
+        let InitialSaslState::Yielded(client_first) = sasl.init() else {
+            panic!("SCRAM starts with the client-first message");
+        };
+        let client_first = String::from_utf8(client_first.to_vec()).unwrap();
+        let client_final = sasl.as_mut().resume(server_first.as_bytes().to_vec()).unwrap();
+        let client_final = String::from_utf8(client_final.response().unwrap()).unwrap();
+        let result = sasl.as_mut().resume(server_final.as_bytes().to_vec());
+        (client_first, client_final, result)
+    }
+
+    // RFC 5802, section 5
+    #[test]
+    fn test_scram_sha_1() {
+        let (client_first, client_final, result) = exchange(
+            ScramHash::Sha1,
+            "fyko+d2lbbFgONRv9qkxdawL",
+            "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096",
+            "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=",
+        );
+        assert_eq!(client_first, "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL");
+        assert_eq!(
+            client_final,
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        assert!(matches!(result, Ok(SaslState::Complete)));
+    }
+
+    // RFC 7677, section 3
+    #[test]
+    fn test_scram_sha_256() {
+        let (client_first, client_final, result) =
+            exchange(ScramHash::Sha256, SHA256_NONCE, SHA256_SERVER_FIRST, SHA256_SERVER_FINAL);
+        assert_eq!(client_first, "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert!(matches!(result, Ok(SaslState::Complete)));
+    }
+
+    #[test]
+    fn test_invalid_server_signature() {
+        let (_, _, result) = exchange(
+            ScramHash::Sha256,
+            SHA256_NONCE,
+            SHA256_SERVER_FIRST,
+            "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=",
+        );
+        assert!(matches!(result, Err(ScramError::InvalidServerSignature)));
+    }
+
+    #[test]
+    fn test_forged_server_signature_closes_connection() {
+        let scram = scram(ScramHash::Sha256, Gs2ChannelBinding::Unsupported, SHA256_NONCE);
+        let server_first = STANDARD.encode(SHA256_SERVER_FIRST);
+        let server_final = STANDARD.encode("v=rmF9pqV8S7suAoZWja4dJRkFsKQ=");
+        let stream = ScriptedStream::new(
+            &[
+                format!("\"{server_first}\"\r\n").as_bytes(),
+                format!("OK (SASL \"{server_final}\")\r\n").as_bytes(),
+            ],
+            usize::MAX,
+        );
+
+        let res = block_on(connection::<Unauthenticated>(stream).authenticate(scram)).unwrap();
+        let Authenticate::Error { connection, error } = res else {
+            panic!("authentication must fail");
+        };
+        assert!(connection.is_none());
+        assert!(matches!(error, SaslError::SaslError(ScramError::InvalidServerSignature)));
+    }
+
+    #[test]
+    fn test_channel_binding() {
+        let data = b"binding data".to_vec();
+        let channel_binding = Gs2ChannelBinding::Used(ChannelBindingType::TlsExporter, data);
+        let scram = scram(ScramHash::Sha256, channel_binding, SHA256_NONCE);
+        assert_eq!(Sasl::name(&scram), "SCRAM-SHA-256-PLUS");
+
+        let (client_first, client_final, _) =
+            exchange_with(scram, SHA256_SERVER_FIRST, SHA256_SERVER_FINAL);
+        assert_eq!(client_first, "p=tls-exporter,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        // the GS2 header followed by the channel binding data
+        let expected = format!(
+            "c={},r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=",
+            STANDARD.encode(b"p=tls-exporter,,binding data")
+        );
+        assert!(client_final.starts_with(&expected), "{client_final}");
+    }
+
+    #[test]
+    fn test_resume_after_completion() {
+        let mut sasl = pin!(scram(ScramHash::Sha256, Gs2ChannelBinding::Unsupported, SHA256_NONCE));
+        sasl.as_mut().resume(SHA256_SERVER_FIRST.as_bytes().to_vec()).unwrap();
+        sasl.as_mut().resume(SHA256_SERVER_FINAL.as_bytes().to_vec()).unwrap();
+        let result = sasl.as_mut().resume(SHA256_SERVER_FINAL.as_bytes().to_vec());
+        assert!(matches!(result, Err(ScramError::Completed)));
+    }
+
+    // the referred server gets a new exchange, although the first one was interrupted after the
+    // client-final message
+    #[test]
+    fn test_authenticate_following_referrals() {
+        let server_first = STANDARD.encode(SHA256_SERVER_FIRST);
+        let server_final = STANDARD.encode(SHA256_SERVER_FINAL);
+        let referring = ScriptedStream::new(
+            &[
+                format!("\"{server_first}\"\r\n").as_bytes(),
+                b"NO (REFERRAL \"sieve://other.example.com\") \"try elsewhere\"\r\n",
+            ],
+            usize::MAX,
+        );
+        let capabilities = "\"IMPLEMENTATION\" \"test\"\r\n\"SIEVE\" \"fileinto\"\r\nOK\r\n";
+        let mut referred = Some(ScriptedStream::new(
+            &[
+                capabilities.as_bytes(),
+                format!("\"{server_first}\"\r\n").as_bytes(),
+                format!("OK (SASL \"{server_final}\")\r\n").as_bytes(),
+                capabilities.as_bytes(),
+            ],
+            usize::MAX,
+        ));
+        let mut referrals = Referrals::new(|host: &str, port| {
+            assert_eq!((host, port), ("other.example.com", 4190));
+            std::future::ready(Ok(referred.take().unwrap()))
+        });
+
+        let res =
+            block_on(connection::<Unauthenticated>(referring).authenticate_following_referrals(
+                || scram(ScramHash::Sha256, Gs2ChannelBinding::Unsupported, SHA256_NONCE),
+                &mut referrals,
+            ))
+            .unwrap();
+        let Authenticate::Ok { connection } = res else {
+            panic!("authentication at the referred server must succeed");
+        };
+        assert!(connection.stream.written.starts_with(b"AUTHENTICATE \"SCRAM-SHA-256\""));
+    }
+}
@@ -0,0 +1,91 @@
+use std::borrow::Cow;
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+    pub fn new(script: impl Into<Vec<u8>>) -> Self {
+        Script(script.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self.0)
+    }
+
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    pub fn into_string(self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.0)
+    }
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(script: Vec<u8>) -> Self {
+        Script(script)
+    }
+}
+
+impl From<String> for Script {
+    fn from(script: String) -> Self {
+        Script(script.into_bytes())
+    }
+}
+
+impl From<&str> for Script {
+    fn from(script: &str) -> Self {
+        Script(script.as_bytes().to_vec())
+    }
+}
+
+impl AsRef<[u8]> for Script {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVALID: &[u8] = b"# \xff\r\nkeep;";
+
+    #[test]
+    fn test_utf8() {
+        let script = Script::from("# \u{e4}\r\nkeep;");
+        assert_eq!(script.to_str().unwrap(), "# \u{e4}\r\nkeep;");
+        assert!(matches!(script.to_string_lossy(), Cow::Borrowed("# \u{e4}\r\nkeep;")));
+        assert_eq!(script.into_string().unwrap(), "# \u{e4}\r\nkeep;");
+    }
+
+    #[test]
+    fn test_to_str_invalid_utf8() {
+        let error = Script::new(INVALID).to_str().unwrap_err();
+        assert_eq!(error.valid_up_to(), 2);
+    }
+
+    #[test]
+    fn test_to_string_lossy_invalid_utf8() {
+        let script = Script::new(INVALID);
+        assert_eq!(script.to_string_lossy(), "# \u{FFFD}\r\nkeep;");
+    }
+
+    #[test]
+    fn test_into_string_invalid_utf8() {
+        let error = Script::new(INVALID).into_string().unwrap_err();
+        assert_eq!(error.utf8_error().valid_up_to(), 2);
+        // the script is not lost
+        assert_eq!(error.into_bytes(), INVALID);
+    }
+}
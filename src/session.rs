@@ -0,0 +1,244 @@
+use std::fmt::{Debug, Formatter};
+
+use crate::commands::{
+    CheckScript, DeleteScript, HaveSpace, Pipelined, PipelinedResponse, PutScript, RenameScript,
+    SetActive,
+};
+use crate::state::{Authenticated, TlsMode};
+use crate::{
+    AsyncRead, AsyncWrite, Capabilities, Connection, Result, Script, SieveError, SieveNameStr,
+    SieveNameString, Timeouts,
+};
+
+/// Authenticated connection with commands taking `&mut self`.
+///
+/// After an error which leaves the connection unusable, the session is poisoned and every further
+/// command fails with [`SieveError::Poisoned`]. If a command future is dropped before it
+/// completed, the next command fails with [`SieveError::Desynchronized`].
+pub struct Session<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> {
+    connection: Connection<STREAM, TLS, Authenticated>,
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Debug for Session<STREAM, TLS> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("connection", &self.connection)
+            .field("poisoned", &self.connection.poisoned)
+            .finish()
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode>
+    From<Connection<STREAM, TLS, Authenticated>> for Session<STREAM, TLS>
+{
+    fn from(connection: Connection<STREAM, TLS, Authenticated>) -> Self {
+        Session { connection }
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Connection<STREAM, TLS, Authenticated> {
+    pub fn into_session(self) -> Session<STREAM, TLS> {
+        Session::from(self)
+    }
+}
+
+impl<STREAM: AsyncRead + AsyncWrite + Unpin, TLS: TlsMode> Session<STREAM, TLS> {
+    pub fn into_connection(mut self) -> Connection<STREAM, TLS, Authenticated> {
+        if self.connection.in_flight {
+            self.connection.poisoned = true;
+        }
+        self.connection
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        self.connection.capabilities()
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Option<Timeouts>) {
+        self.connection.set_timeouts(timeouts);
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.connection.poisoned
+    }
+
+    pub async fn list_scripts(&mut self) -> Result<Vec<(SieveNameString, bool)>> {
+        self.run(async |connection| connection.list_scripts_inner().await).await
+    }
+
+    pub async fn have_space(&mut self, name: &SieveNameStr, size: u32) -> Result<HaveSpace> {
+        self.run(async |connection| connection.have_space_inner(name, size).await).await
+    }
+
+    pub async fn get_script(&mut self, name: &SieveNameStr) -> Result<Option<Script>> {
+        self.run(async |connection| connection.get_script_inner(name).await).await
+    }
+
+    pub async fn get_script_to(
+        &mut self,
+        name: &SieveNameStr,
+        sink: impl AsyncWrite + Unpin,
+    ) -> Result<Option<u64>> {
+        self.run(async |connection| connection.get_script_to_inner(name, sink).await)
+            .await
+    }
+
+    pub async fn put_scripts(
+        &mut self,
+        name: &SieveNameStr,
+        script: impl AsRef<[u8]>,
+    ) -> Result<PutScript> {
+        self.run(async |connection| connection.put_script_inner(name, script.as_ref()).await)
+            .await
+    }
+
+    pub async fn put_script_from(
+        &mut self,
+        name: &SieveNameStr,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<PutScript> {
+        self.run(async |connection| connection.put_script_from_inner(name, len, script).await)
+            .await
+    }
+
+    pub async fn check_script(&mut self, script: impl AsRef<[u8]>) -> Result<CheckScript> {
+        self.run(async |connection| connection.check_script_inner(script.as_ref()).await)
+            .await
+    }
+
+    pub async fn check_script_from(
+        &mut self,
+        len: u32,
+        script: impl AsyncRead + Unpin,
+    ) -> Result<CheckScript> {
+        self.run(async |connection| connection.check_script_from_inner(len, script).await)
+            .await
+    }
+
+    pub async fn set_active(&mut self, name: &SieveNameStr) -> Result<SetActive> {
+        self.run(async |connection| connection.set_active_inner(name).await).await
+    }
+
+    pub async fn deactivate_all(&mut self) -> Result<SetActive> {
+        self.run(async |connection| connection.deactivate_all_inner().await).await
+    }
+
+    pub async fn delete_script(&mut self, name: &SieveNameStr) -> Result<DeleteScript> {
+        self.run(async |connection| connection.delete_script_inner(name).await).await
+    }
+
+    pub async fn rename_script(
+        &mut self,
+        old_name: &SieveNameStr,
+        new_name: &SieveNameStr,
+    ) -> Result<RenameScript> {
+        self.run(async |connection| connection.rename_script_inner(old_name, new_name).await)
+            .await
+    }
+
+    pub async fn pipeline(&mut self, commands: &[Pipelined<'_>]) -> Result<Vec<PipelinedResponse>> {
+        self.run(async |connection| connection.pipeline_inner(commands).await).await
+    }
+
+    pub async fn get_scripts(&mut self, names: &[&SieveNameStr]) -> Result<Vec<Option<Script>>> {
+        self.run(async |connection| connection.get_scripts_inner(names).await).await
+    }
+
+    pub async fn noop(&mut self, tag: Option<&str>) -> Result<()> {
+        self.run(async |connection| connection.noop_inner(tag).await).await
+    }
+
+    pub async fn refresh_capabilities(&mut self) -> Result<()> {
+        self.run(async |connection| connection.refresh_capabilities_inner().await).await
+    }
+
+    // Marks the command as in flight, so it is noticed if its future is dropped before completion.
+    async fn run<T>(
+        &mut self,
+        command: impl AsyncFnOnce(&mut Connection<STREAM, TLS, Authenticated>) -> Result<T>,
+    ) -> Result<T> {
+        if self.connection.in_flight {
+            // reported once, later commands fail with `SieveError::Poisoned`
+            self.connection.in_flight = false;
+            self.connection.poisoned = true;
+            return Err(SieveError::Desynchronized);
+        }
+
+        self.connection.in_flight = true;
+        let res = command(&mut self.connection).await;
+        self.connection.in_flight = false;
+        self.connection.poison_on_fatal(res)
+    }
+
+    pub async fn logout(self) -> Result<()> {
+        self.into_connection().logout().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::state::NoTls;
+    use crate::test_util::{connection, End, ScriptedStream};
+
+    fn session(reads: &[&[u8]], end: End) -> Session<ScriptedStream, NoTls> {
+        connection(ScriptedStream::new(reads, 1024).with_end(end)).into_session()
+    }
+
+    #[test]
+    fn test_dropped_command_desynchronizes() {
+        let mut session = session(&[], End::Pending);
+
+        // the server never answers, so the command is still waiting for its response
+        assert!(session.noop(None).now_or_never().is_none());
+        assert!(!session.is_poisoned());
+
+        let error = block_on(session.noop(None)).unwrap_err();
+        assert!(matches!(error, SieveError::Desynchronized), "{error:?}");
+        assert!(session.is_poisoned());
+
+        let error = block_on(session.list_scripts()).unwrap_err();
+        assert!(matches!(error, SieveError::Poisoned), "{error:?}");
+        assert_eq!(session.connection.stream.written, b"NOOP\r\n");
+    }
+
+    #[test]
+    fn test_dropped_command_poisons_connection() {
+        let mut session = session(&[], End::Pending);
+        assert!(session.noop(None).now_or_never().is_none());
+
+        let connection = session.into_connection();
+        let error = block_on(connection.noop(None)).unwrap_err();
+        assert!(matches!(error.error, SieveError::Poisoned), "{error:?}");
+    }
+
+    #[test]
+    fn test_io_error_poisons() {
+        let mut session = session(&[b"OK\r\n"], End::Error);
+
+        block_on(session.noop(None)).unwrap();
+        let error = block_on(session.noop(None)).unwrap_err();
+        assert!(matches!(error, SieveError::Io(_)), "{error:?}");
+        assert!(session.is_poisoned());
+        assert!(session.connection.stream.closed);
+
+        let error = block_on(session.noop(None)).unwrap_err();
+        assert!(matches!(error, SieveError::Poisoned), "{error:?}");
+        assert_eq!(session.connection.stream.written, b"NOOP\r\nNOOP\r\n");
+    }
+
+    #[test]
+    fn test_no_response_keeps_session_usable() {
+        let mut session = session(&[b"NO \"busy\"\r\n", b"OK\r\n"], End::Eof);
+
+        let error = block_on(session.noop(None)).unwrap_err();
+        assert!(matches!(error, SieveError::UnexpectedNo { .. }), "{error:?}");
+        assert!(!session.is_poisoned());
+
+        block_on(session.noop(None)).unwrap();
+    }
+}
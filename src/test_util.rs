@@ -0,0 +1,129 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::state::{AuthMode, NoTls};
+use crate::{AsyncRead, AsyncWrite, Capabilities, Connection, Version};
+
+/// What a [`ScriptedStream`] does once all scripted reads are consumed.
+pub(crate) enum End {
+    Eof,
+    /// never completes, like a server which stopped responding
+    Pending,
+    Error,
+}
+
+/// Stream returning the scripted reads in order, at most `chunk` bytes per read.
+pub(crate) struct ScriptedStream {
+    reads: VecDeque<Vec<u8>>,
+    chunk: usize,
+    end: End,
+    // writes fail, like a connection reset by the server
+    fail_writes: bool,
+    pub(crate) written: Vec<u8>,
+    pub(crate) closed: bool,
+}
+
+impl ScriptedStream {
+    pub(crate) fn new(reads: &[&[u8]], chunk: usize) -> Self {
+        ScriptedStream {
+            reads: reads.iter().map(|read| read.to_vec()).collect(),
+            chunk,
+            end: End::Eof,
+            fail_writes: false,
+            written: Vec::new(),
+            closed: false,
+        }
+    }
+
+    pub(crate) fn with_end(mut self, end: End) -> Self {
+        self.end = end;
+        self
+    }
+
+    pub(crate) fn with_failing_writes(mut self) -> Self {
+        self.fail_writes = true;
+        self
+    }
+}
+
+impl AsyncRead for ScriptedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let chunk = self.chunk;
+        let Some(read) = self.reads.front_mut() else {
+            return match self.end {
+                End::Eof => Poll::Ready(Ok(0)),
+                End::Pending => Poll::Pending,
+                End::Error => Poll::Ready(Err(io::ErrorKind::ConnectionReset.into())),
+            };
+        };
+        let read_count = buf.len().min(chunk).min(read.len());
+        buf[..read_count].copy_from_slice(&read[..read_count]);
+        read.drain(..read_count);
+        if read.is_empty() {
+            self.reads.pop_front();
+        }
+        Poll::Ready(Ok(read_count))
+    }
+}
+
+impl AsyncWrite for ScriptedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.fail_writes {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        self.written.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub(crate) fn capabilities() -> Capabilities {
+    Capabilities {
+        implementation: "test".to_owned(),
+        sasl: Vec::new(),
+        sieve: Vec::new(),
+        start_tls: false,
+        max_redirects: None,
+        notify: None,
+        language: None,
+        owner: None,
+        version: Version { major: 1, minor: 0 },
+        others: HashMap::new(),
+    }
+}
+
+/// Connection in any state over `stream`, as if the server greeting was already read.
+pub(crate) fn connection<MODE: AuthMode>(
+    stream: ScriptedStream,
+) -> Connection<ScriptedStream, NoTls, MODE> {
+    Connection {
+        stream,
+        capabilities: capabilities(),
+        last_command: None,
+        poisoned: false,
+        read_buf: Vec::new(),
+        write_buf: Vec::new(),
+        timeouts: None,
+        tls_config: None,
+        in_flight: false,
+        _p: Default::default(),
+    }
+}
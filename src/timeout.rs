@@ -0,0 +1,196 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::{select, Either};
+
+use crate::SieveError;
+
+/// Source of sleep futures, so timeouts work with any async runtime.
+pub trait Timer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+impl<F: Fn(Duration) -> FUT, FUT: Future<Output = ()> + Send + 'static> Timer for F {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(self(duration))
+    }
+}
+
+#[derive(Clone)]
+pub struct Timeouts {
+    pub timer: Arc<dyn Timer + Send + Sync>,
+    /// Maximum time a single read of the server greeting may take.
+    ///
+    /// The timeout restarts whenever data is received, so a server sending its greeting slowly may
+    /// take longer as a whole. The TLS handshake of implicit TLS must complete within this time as
+    /// a whole.
+    pub greeting: Option<Duration>,
+    /// Maximum time a single read or write may take while a command runs.
+    ///
+    /// The timeout restarts whenever data is transferred, so sending or receiving a large script
+    /// over a slow link may take longer as long as the data keeps flowing. The TLS handshake after
+    /// `STARTTLS` must complete within this time as a whole.
+    pub command: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new(timer: impl Timer + Send + Sync + 'static) -> Self {
+        Timeouts {
+            timer: Arc::new(timer),
+            greeting: Some(Duration::from_secs(30)),
+            command: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl Debug for Timeouts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Timeouts")
+            .field("greeting", &self.greeting)
+            .field("command", &self.command)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) async fn with_timeout<T>(
+    timeouts: Option<&Timeouts>,
+    duration: impl FnOnce(&Timeouts) -> Option<Duration>,
+    future: impl Future<Output = Result<T, SieveError>>,
+) -> Result<T, SieveError> {
+    let Some((timer, duration)) =
+        timeouts.and_then(|timeouts| Some((&timeouts.timer, duration(timeouts)?)))
+    else {
+        return future.await;
+    };
+
+    match select(pin!(future), timer.sleep(duration)).await {
+        Either::Left((res, _)) => res,
+        Either::Right(((), _)) => Err(SieveError::Timeout),
+    }
+}
+
+// Timeout which restarts whenever the guarded I/O makes progress.
+pub(crate) struct IdleTimeout {
+    timer: Arc<dyn Timer + Send + Sync>,
+    duration: Duration,
+    sleep: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl IdleTimeout {
+    pub(crate) fn new(
+        timeouts: Option<&Timeouts>,
+        duration: impl FnOnce(&Timeouts) -> Option<Duration>,
+    ) -> Option<Self> {
+        let timeouts = timeouts?;
+        Some(IdleTimeout {
+            timer: timeouts.timer.clone(),
+            duration: duration(timeouts)?,
+            sleep: None,
+        })
+    }
+
+    pub(crate) fn poll_elapsed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let (timer, duration) = (&self.timer, self.duration);
+        self.sleep.get_or_insert_with(|| timer.sleep(duration)).as_mut().poll(cx)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::commands::ConnectOptions;
+    use crate::state::{Authenticated, NoTls, Unauthenticated};
+    use crate::test_util::{connection, End, ScriptedStream};
+    use crate::Connection;
+
+    // Timer whose sleeps elapse immediately, recording the requested durations.
+    fn timeouts() -> (Timeouts, Arc<Mutex<Vec<Duration>>>) {
+        let sleeps = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sleeps.clone();
+        let timeouts = Timeouts {
+            timer: Arc::new(move |duration| {
+                recorded.lock().unwrap().push(duration);
+                async {}
+            }),
+            greeting: Some(Duration::from_secs(1)),
+            command: Some(Duration::from_secs(2)),
+        };
+        (timeouts, sleeps)
+    }
+
+    #[test]
+    fn test_greeting_timeout() {
+        let (timeouts, sleeps) = timeouts();
+        let stream =
+            ScriptedStream::new(&[b"\"IMPLEMENTATION\" \"test\"\r\n"], 1024).with_end(End::Pending);
+        let options = ConnectOptions {
+            tls_config: None,
+            timeouts: Some(timeouts),
+        };
+
+        let res = block_on(Connection::<_, NoTls, Unauthenticated>::connect_with_options(
+            stream, options,
+        ));
+        assert!(matches!(res, Err(SieveError::Timeout)), "{res:?}");
+        assert_eq!(*sleeps.lock().unwrap(), [Duration::from_secs(1)]);
+    }
+
+    #[test]
+    fn test_command_timeout_closes_connection() {
+        let (timeouts, sleeps) = timeouts();
+        let mut connection = connection::<Authenticated>(
+            ScriptedStream::new(&[b"OK\r\n"], 1024).with_end(End::Pending),
+        );
+        connection.set_timeouts(Some(timeouts));
+
+        let connection = block_on(connection.noop(None)).unwrap();
+        let error = block_on(connection.noop(None)).unwrap_err();
+        assert!(matches!(error.error, SieveError::Timeout), "{error:?}");
+        assert!(error.connection.is_none());
+        // writing the command is limited by the command timeout as well
+        let sleeps = sleeps.lock().unwrap();
+        assert!(!sleeps.is_empty());
+        assert!(sleeps.iter().all(|&duration| duration == Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_command_timeout_poisons_session() {
+        let (timeouts, _) = timeouts();
+        let mut session =
+            connection(ScriptedStream::new(&[], 1024).with_end(End::Pending)).into_session();
+        session.set_timeouts(Some(timeouts));
+
+        let error = block_on(session.noop(None)).unwrap_err();
+        assert!(matches!(error, SieveError::Timeout), "{error:?}");
+        assert!(session.is_poisoned());
+
+        let connection = session.into_connection();
+        assert!(connection.stream.closed);
+        let error = block_on(connection.noop(None)).unwrap_err();
+        assert!(matches!(error.error, SieveError::Poisoned), "{error:?}");
+        assert!(error.connection.is_none());
+    }
+
+    #[test]
+    fn test_no_timeout_without_duration() {
+        let (mut timeouts, sleeps) = timeouts();
+        timeouts.command = None;
+        let mut connection = connection::<Authenticated>(ScriptedStream::new(&[b"O", b"K\r\n"], 1));
+        connection.set_timeouts(Some(timeouts));
+
+        block_on(connection.noop(None)).unwrap();
+        assert!(sleeps.lock().unwrap().is_empty());
+    }
+}